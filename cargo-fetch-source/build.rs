@@ -7,4 +7,9 @@ fn main() {
         .expect("Failed to add git instructions")
         .emit()
         .expect("Failed to emit git instructions");
+    // The triple of the platform the binary will run on
+    println!(
+        "cargo:rustc-env=HOST_TARGET={}",
+        std::env::var("TARGET").expect("cargo sets TARGET for build scripts")
+    );
 }
@@ -6,7 +6,9 @@ use clap::FromArgMatches;
 use clap::{CommandFactory, Parser};
 use fetch_source::GitReference;
 
+use crate::edit::NewSource;
 use crate::error::AppError;
+use crate::link::LinkMode;
 
 // Shamelessly borrowed from https://github.com/crate-ci/clap-cargo/blob/0378657ffdf2b67bcd6f1ab56e04a1322b92dd0e/src/style.rs
 // thanks to https://stackoverflow.com/a/79614957
@@ -52,9 +54,29 @@ enum Command {
     Fetch {
         /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
         /// directories.
-        #[arg(long, short = 'm', value_name = "PATH", global = true)]
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH",
+            global = true
+        )]
         manifest_file: Option<PathBuf>,
 
+        #[command(flatten)]
+        packages: PackageArgs,
+
+        /// Comma-separated list of enabled features. Sources which declare `features` are only
+        /// fetched if one of them is enabled. If omitted, every source is fetched.
+        #[arg(long, short = 'F', value_name = "FEATURES", value_delimiter = ',')]
+        features: Option<Vec<String>>,
+
+        /// Only fetch sources which are needed for this target triple. Sources which declare
+        /// `target` are evaluated using `rustc --print cfg`. If omitted, every source is fetched.
+        /// Also sets `${target}` in source definitions, which is otherwise the host triple.
+        #[arg(long, value_name = "TRIPLE")]
+        target: Option<String>,
+
         /// Output directory where the fetched sources should be copied to once cached.
         #[arg(long, short = 'o', value_name = "PATH")]
         out_dir: Option<PathBuf>,
@@ -64,6 +86,73 @@ enum Command {
         #[arg(long = "cache", short = 'c', value_name = "PATH")]
         cache_dir: Option<PathBuf>,
 
+        /// Read-only cache directory to search before the writable cache. May be given multiple
+        /// times. If omitted, check the `CARGO_FETCH_SOURCE_READ_ONLY_CACHE` environment variable,
+        /// which may contain a list of directories.
+        #[arg(long = "read-only-cache", value_name = "PATH")]
+        read_only_cache_dirs: Vec<PathBuf>,
+
+        /// Directory containing vendored sources, which are used instead of fetching. Defaults to
+        /// `fetch-source-vendor` next to the manifest.
+        #[arg(long, value_name = "PATH")]
+        vendor_dir: Option<PathBuf>,
+
+        /// Store files which are identical to those in other cached sources only once, by
+        /// hard-linking them to a content-addressed store in the cache directory.
+        #[arg(long)]
+        dedup: bool,
+
+        /// How to materialise sources in the output directory. Falls back to copying where the
+        /// chosen mode isn't supported.
+        #[arg(long, value_enum, value_name = "MODE", default_value_t = LinkMode::Copy, requires = "out_dir")]
+        link_mode: LinkMode,
+
+        /// Make each source's directory in the output directory exactly match the cached source,
+        /// removing stale files and only rewriting files which changed. Refuses to overwrite
+        /// directories which were modified since they were last synced.
+        #[arg(long, requires = "out_dir", conflicts_with = "link_mode")]
+        sync: bool,
+
+        /// With `--sync`, overwrite directories even if they were modified locally.
+        #[arg(long, requires = "sync")]
+        force: bool,
+
+        /// Number of threads to spawn. Defaults to one per logical CPU.
+        #[arg(long, short = 't', value_name = "NUM-THREADS")]
+        threads: Option<u32>,
+    },
+    /// Copy the sources specified in the manifest into a project-local directory, fetching and
+    /// caching them as needed. Vendored sources are used by `fetch` instead of fetching them.
+    Vendor {
+        /// Directory to vendor sources into. Defaults to `fetch-source-vendor` next to the
+        /// manifest.
+        #[arg(value_name = "DIR")]
+        vendor_dir: Option<PathBuf>,
+
+        /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
+        /// directories.
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH"
+        )]
+        manifest_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        packages: PackageArgs,
+
+        /// Cache directory to use. If omitted, check the `CARGO_FETCH_SOURCE_CACHE` environment
+        /// variable and then `~/.cache/cargo-fetch-source`
+        #[arg(long = "cache", short = 'c', value_name = "PATH")]
+        cache_dir: Option<PathBuf>,
+
+        /// Read-only cache directory to search before the writable cache. May be given multiple
+        /// times. If omitted, check the `CARGO_FETCH_SOURCE_READ_ONLY_CACHE` environment variable,
+        /// which may contain a list of directories.
+        #[arg(long = "read-only-cache", value_name = "PATH")]
+        read_only_cache_dirs: Vec<PathBuf>,
+
         /// Number of threads to spawn. Defaults to one per logical CPU.
         #[arg(long, short = 't', value_name = "NUM-THREADS")]
         threads: Option<u32>,
@@ -72,30 +161,177 @@ enum Command {
     List {
         /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
         /// directories.
-        #[arg(long, short = 'm', value_name = "PATH", global = true)]
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH",
+            global = true
+        )]
         manifest_file: Option<PathBuf>,
 
+        #[command(flatten)]
+        packages: PackageArgs,
+
         /// Output format
         #[arg(long, short = 'f', value_enum, value_name = "FORMAT")]
         format: Option<OutputFormat>,
     },
+    /// Check the sources specified in the manifest for problems without fetching them. Exits with
+    /// a non-zero status if the manifest is invalid or any lint is denied.
+    ///
+    /// Lints: insecure-url, floating-reference, missing-checksum, duplicate-source. Every lint is
+    /// a warning by default.
+    Check {
+        /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
+        /// directories.
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH"
+        )]
+        manifest_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        packages: PackageArgs,
+
+        /// Don't check the given lint, or `warnings` for every lint which is a warning. May be
+        /// given multiple times.
+        #[arg(long, short = 'A', value_name = "LINT", value_parser = parse_lint_selection)]
+        allow: Vec<LintSelection>,
+
+        /// Report the given lint as a warning, or `warnings` for every lint. May be given multiple
+        /// times.
+        #[arg(long, short = 'W', value_name = "LINT", value_parser = parse_lint_selection)]
+        warn: Vec<LintSelection>,
+
+        /// Report the given lint as an error, or `warnings` for every lint which is a warning. May
+        /// be given multiple times.
+        #[arg(long, short = 'D', value_name = "LINT", value_parser = parse_lint_selection)]
+        deny: Vec<LintSelection>,
+    },
+    /// Add a source to the manifest, preserving its formatting. Creates the `fetch-source` table if
+    /// it doesn't exist.
+    Add {
+        /// Name of the source to add
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
+        /// directories.
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH"
+        )]
+        manifest_file: Option<PathBuf>,
+
+        /// The URL of a git repository to add. Conflicts with --tar.
+        #[arg(
+            long = "git",
+            value_name = "URL",
+            required_unless_present = "tar",
+            conflicts_with = "tar"
+        )]
+        git: Option<String>,
+
+        /// The branch to clone. Only valid with --git.
+        #[arg(long = "branch", short = 'b', value_name = "BRANCH", conflicts_with_all = ["tar", "tag", "rev"])]
+        branch: Option<String>,
+
+        /// The tag to clone. Only valid with --git. Conflicts with --branch.
+        #[arg(long = "tag", short = 't', value_name = "TAG", conflicts_with_all = ["tar", "branch", "rev"])]
+        tag: Option<String>,
+
+        /// The commit SHA to clone. Only valid with --git. Conflicts with --branch and --tag.
+        #[arg(long = "rev", short = 'r', value_name = "REV", conflicts_with_all = ["tar", "branch", "tag"])]
+        rev: Option<String>,
+
+        /// Recursively clone submodules. Only valid with --git.
+        #[arg(long = "recursive", conflicts_with = "tar")]
+        recursive: bool,
+
+        /// The URL of a tar archive to add. Conflicts with --git.
+        #[arg(long = "tar", value_name = "URL")]
+        tar: Option<String>,
+
+        /// The expected SHA-256 digest of the archive. If given without a value, download the
+        /// archive to compute it. Only valid with --tar.
+        #[arg(long, value_name = "SHA256", num_args = 0..=1, conflicts_with = "git")]
+        sha256: Option<Option<String>>,
+
+        /// Where to copy the source to, relative to the output directory.
+        #[arg(long, value_name = "PATH")]
+        dest: Option<PathBuf>,
+    },
+    /// Remove sources from the manifest, preserving its formatting.
+    Remove {
+        /// Names of the sources to remove
+        #[arg(value_name = "NAME", required = true)]
+        names: Vec<String>,
+
+        /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
+        /// directories.
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH"
+        )]
+        manifest_file: Option<PathBuf>,
+
+        /// Also remove the sources from the cache, unless the same source is still declared in the
+        /// manifest, elsewhere in its workspace, or in a manifest given with --other-manifest.
+        #[arg(long)]
+        evict: bool,
+
+        /// Another manifest whose sources should be kept in the cache. May be given multiple
+        /// times. Only valid with --evict.
+        #[arg(long, value_name = "PATH", requires = "evict")]
+        other_manifest: Vec<PathBuf>,
+
+        /// Cache directory to evict sources from. If omitted, check the `CARGO_FETCH_SOURCE_CACHE`
+        /// environment variable and then `~/.cache/cargo-fetch-source`. Only valid with --evict.
+        #[arg(long = "cache", short = 'c', value_name = "PATH", requires = "evict")]
+        cache_dir: Option<PathBuf>,
+    },
+    /// Print a JSON Schema of `Cargo.toml` describing the `fetch-source` metadata tables, for
+    /// editors to validate and complete source definitions.
+    Schema {
+        /// Only describe the contents of the `fetch-source` table rather than the whole manifest.
+        #[arg(long)]
+        table: bool,
+    },
     /// List or query cached sources. When querying, both `--manifest-file` and `--source` are
     /// required.
+    #[command(args_conflicts_with_subcommands = true)]
     Cached {
+        #[command(subcommand)]
+        action: Option<CachedAction>,
+
         /// Output format
         #[arg(long, short = 'f', value_enum, value_name = "FORMAT")]
         format: Option<OutputFormat>,
 
         /// Cache directory to use. If omitted, check the `CARGO_FETCH_SOURCE_CACHE` environment
         /// variable and then `~/.cache/cargo-fetch-source`
-        #[arg(long = "cache", short = 'c', value_name = "PATH")]
+        #[arg(long = "cache", short = 'c', value_name = "PATH", global = true)]
         cache_dir: Option<PathBuf>,
 
+        /// Read-only cache directory to search before the writable cache. May be given multiple
+        /// times. If omitted, check the `CARGO_FETCH_SOURCE_READ_ONLY_CACHE` environment variable,
+        /// which may contain a list of directories.
+        #[arg(long = "read-only-cache", value_name = "PATH", global = true)]
+        read_only_cache_dirs: Vec<PathBuf>,
+
         /// Manifest file to use when querying the cache for a specific source. Required if
         /// `--source` is given.
         #[arg(
             long,
             short = 'm',
+            visible_alias = "manifest-path",
             value_name = "PATH",
             group = "query-manifest",
             requires = "query-source"
@@ -113,6 +349,9 @@ enum Command {
         )]
         source: Option<String>,
 
+        #[command(flatten)]
+        packages: PackageArgs,
+
         /// A tar URL to query from the cache. Cannot be used with --manifest-file or --source.
         #[arg(long = "tar", value_name = "URL", group = "query-tar", conflicts_with_all = ["manifest_file", "source"])]
         tar: Option<String>,
@@ -139,6 +378,88 @@ enum Command {
     },
 }
 
+#[derive(Debug, clap::Subcommand)]
+enum CachedAction {
+    /// Export cached sources into a bundle which can be imported into another cache. By default,
+    /// export every source in the manifest.
+    Export {
+        /// Path of the bundle file to create
+        #[arg(value_name = "BUNDLE")]
+        bundle: PathBuf,
+
+        /// Path to the Cargo.toml file. If not given, search for the file in the current and parent
+        /// directories.
+        #[arg(
+            long,
+            short = 'm',
+            visible_alias = "manifest-path",
+            value_name = "PATH"
+        )]
+        manifest_file: Option<PathBuf>,
+
+        #[command(flatten)]
+        packages: PackageArgs,
+
+        /// Name of a source in the manifest to export. May be given multiple times. If omitted,
+        /// export all sources in the manifest.
+        #[arg(long = "source", short = 's', value_name = "SOURCE")]
+        sources: Vec<String>,
+
+        /// Export every source in the writable cache instead of the sources in a manifest.
+        #[arg(long, conflicts_with_all = ["manifest_file", "sources", "workspace", "cargo_metadata", "package"])]
+        all: bool,
+    },
+    /// Import the sources in a bundle into the cache, skipping any which are already cached
+    Import {
+        /// Path of the bundle file to import
+        #[arg(value_name = "BUNDLE")]
+        bundle: PathBuf,
+    },
+    /// Store files which are identical between cached sources only once, by hard-linking them to
+    /// a content-addressed store in the cache directory. Unused files are removed from the store.
+    Dedup,
+    /// Pack cached sources which haven't been used recently into compressed archives to save
+    /// space. Packed sources are unpacked when they are next used.
+    Pack {
+        /// Pack sources which haven't been fetched or used for at least this many days.
+        #[arg(long, value_name = "DAYS", default_value_t = 30)]
+        unused_for: u64,
+    },
+    /// Merge another cache into the cache. Sources missing from the cache are copied. Where both
+    /// caches contain the same source, the most recently fetched copy is kept.
+    Merge {
+        /// Directory of the cache to merge from. This cache is not modified.
+        #[arg(value_name = "OTHER_CACHE_DIR")]
+        other_cache_dir: PathBuf,
+    },
+}
+
+/// Which packages to read sources from, following cargo's package selection flags
+#[derive(Debug, clap::Args)]
+struct PackageArgs {
+    /// Use the sources declared by every member of the workspace containing the manifest, and in
+    /// the `workspace.metadata.fetch-source` table of the workspace root.
+    #[arg(long)]
+    workspace: bool,
+
+    /// Locate packages and their sources with `cargo metadata`, exactly as cargo sees them.
+    /// Without `--package` or `--workspace`, selects the package of the manifest, or every
+    /// workspace member if the manifest is a virtual manifest.
+    #[arg(long)]
+    cargo_metadata: bool,
+
+    /// Use the sources of the named workspace member. May be given multiple times. Implies
+    /// `--cargo-metadata`.
+    #[arg(long, short = 'p', value_name = "NAME", conflicts_with = "workspace")]
+    package: Vec<String>,
+}
+
+impl PackageArgs {
+    fn is_given(&self) -> bool {
+        self.workspace || self.cargo_metadata || !self.package.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, clap::ValueEnum)]
 pub enum OutputFormat {
     /// Output in JSON format
@@ -147,36 +468,145 @@ pub enum OutputFormat {
     Toml,
 }
 
+/// A lint named on the command line, or `warnings` for every lint which is a warning
+#[derive(Debug, Clone, Copy)]
+pub enum LintSelection {
+    Warnings,
+    Lint(fetch_source::Lint),
+}
+
+fn parse_lint_selection(name: &str) -> Result<LintSelection, String> {
+    match name {
+        "warnings" => Ok(LintSelection::Warnings),
+        name => name.parse().map(LintSelection::Lint),
+    }
+}
+
+/// Which manifests to read sources from
+#[derive(Debug)]
+pub enum SourceSelection {
+    /// The `package.metadata.fetch-source` table of a single manifest
+    Manifest,
+    /// Every manifest in a workspace
+    Workspace(fetch_source::Workspace),
+    /// Packages reported by `cargo metadata`, and the workspace sources if `include_workspace`
+    CargoMetadata {
+        metadata: fetch_source::CargoMetadata,
+        packages: Vec<fetch_source::CargoPackage>,
+        include_workspace: bool,
+    },
+}
+
 #[derive(Debug)]
 pub struct ValidatedArgs {
     pub command: ValidatedCommand,
+    /// The variables expanded in source definitions
+    pub variables: fetch_source::Variables,
 }
 
 #[derive(Debug)]
 pub enum CacheQuery {
     Manifest {
         manifest_file: PathBuf,
+        selection: SourceSelection,
         source: String,
     },
     Source(fetch_source::Source),
 }
 
+#[derive(Debug)]
+pub enum ExportSelection {
+    /// Every source in the writable cache
+    All,
+    /// The named sources in a manifest, or all of them if none are named
+    Manifest {
+        manifest_file: PathBuf,
+        selection: SourceSelection,
+        sources: Vec<String>,
+    },
+}
+
+/// Where to evict removed sources from, and the manifests whose sources must be kept
+#[derive(Debug)]
+pub struct Eviction {
+    pub cache: fetch_source::Cache,
+    pub other_manifests: Vec<PathBuf>,
+}
+
+// Only one command is ever constructed, so the size of the largest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum ValidatedCommand {
     Fetch {
         manifest_file: PathBuf,
+        selection: SourceSelection,
+        conditions: fetch_source::Conditions,
         out_dir: Option<PathBuf>,
         cache: fetch_source::Cache,
+        vendor: Option<fetch_source::Vendor>,
+        dedup: bool,
+        link_mode: LinkMode,
+        sync: bool,
+        force: bool,
+    },
+    Vendor {
+        manifest_file: PathBuf,
+        selection: SourceSelection,
+        cache: fetch_source::Cache,
+        vendor: fetch_source::Vendor,
     },
     List {
         manifest_file: PathBuf,
+        selection: SourceSelection,
         format: Option<OutputFormat>,
     },
+    Check {
+        manifest_file: PathBuf,
+        selection: SourceSelection,
+        lints: fetch_source::Lints,
+        vendor: Option<fetch_source::Vendor>,
+    },
+    Add {
+        manifest_file: PathBuf,
+        name: String,
+        source: NewSource,
+        /// Whether to download the archive to compute its digest
+        compute_sha256: bool,
+        dest: Option<PathBuf>,
+    },
+    Remove {
+        manifest_file: PathBuf,
+        names: Vec<String>,
+        eviction: Option<Eviction>,
+    },
+    Schema {
+        table: bool,
+    },
     Cached {
         format: Option<OutputFormat>,
         cache: fetch_source::Cache,
         query_args: Option<CacheQuery>,
     },
+    Export {
+        cache: fetch_source::Cache,
+        bundle: PathBuf,
+        selection: ExportSelection,
+    },
+    Import {
+        cache: fetch_source::Cache,
+        bundle: PathBuf,
+    },
+    Merge {
+        cache: fetch_source::Cache,
+        other: fetch_source::Cache,
+    },
+    Dedup {
+        cache: fetch_source::Cache,
+    },
+    Pack {
+        cache: fetch_source::Cache,
+        unused_for: std::time::Duration,
+    },
 }
 
 impl ValidatedArgs {
@@ -207,6 +637,91 @@ impl ValidatedArgs {
         }
     }
 
+    /// Select packages using `cargo metadata`: every workspace member with `workspace`, else the
+    /// named packages, else the package of `manifest_file` (or every member if it is a virtual
+    /// manifest).
+    fn select_cargo_packages(
+        manifest_file: PathBuf,
+        names: Vec<String>,
+        workspace: bool,
+    ) -> Result<(PathBuf, SourceSelection), AppError> {
+        let metadata = fetch_source::CargoMetadata::load(&manifest_file).map_err(|e| {
+            AppError::arg_validation(format!(
+                "failed to get cargo metadata for {}: {}",
+                manifest_file.display(),
+                e
+            ))
+        })?;
+        let packages = if workspace {
+            metadata.packages().to_vec()
+        } else if !names.is_empty() {
+            names
+                .iter()
+                .map(|name| {
+                    metadata.package(name).cloned().ok_or_else(|| {
+                        AppError::arg_validation(format!(
+                            "no package called '{name}' in the workspace"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            let manifest = manifest_file.canonicalize()?;
+            match metadata
+                .packages()
+                .iter()
+                .find(|p| p.manifest_path() == manifest)
+            {
+                Some(package) => vec![package.clone()],
+                None => metadata.packages().to_vec(),
+            }
+        };
+        let manifest_file = if workspace {
+            metadata.workspace_root().join("Cargo.toml")
+        } else {
+            manifest_file
+        };
+        Ok((
+            manifest_file,
+            SourceSelection::CargoMetadata {
+                metadata,
+                packages,
+                include_workspace: workspace,
+            },
+        ))
+    }
+
+    /// Select the manifests to read sources from. In a workspace, the root manifest takes the
+    /// place of the package manifest.
+    fn select_sources(
+        manifest_file: Option<PathBuf>,
+        packages: PackageArgs,
+    ) -> Result<(PathBuf, SourceSelection), AppError> {
+        let PackageArgs {
+            workspace,
+            cargo_metadata,
+            package,
+        } = packages;
+        let manifest_file = ValidatedArgs::detect_manifest_file(manifest_file)?;
+        if cargo_metadata || !package.is_empty() {
+            ValidatedArgs::select_cargo_packages(manifest_file, package, workspace)
+        } else if workspace {
+            let workspace = fetch_source::Workspace::find(&manifest_file).map_err(|e| {
+                AppError::arg_validation(format!(
+                    "failed to find workspace for {}: {}",
+                    manifest_file.display(),
+                    e
+                ))
+            })?;
+            Ok((
+                workspace.root_manifest().to_path_buf(),
+                SourceSelection::Workspace(workspace),
+            ))
+        } else {
+            Ok((manifest_file, SourceSelection::Manifest))
+        }
+    }
+
     /// Detect the cache directory, falling back to `CARGO_FETCH_SOURCE_CACHE` then
     /// ~/.cache/cargo-fetch-source
     fn detect_cache_dir(arg: Option<PathBuf>) -> Result<PathBuf, AppError> {
@@ -225,6 +740,119 @@ impl ValidatedArgs {
         }
     }
 
+    /// Detect the read-only cache directories, falling back to `CARGO_FETCH_SOURCE_READ_ONLY_CACHE`
+    fn detect_read_only_cache_dirs(arg: Vec<PathBuf>) -> Vec<PathBuf> {
+        if !arg.is_empty() {
+            return arg;
+        }
+        match std::env::var_os("CARGO_FETCH_SOURCE_READ_ONLY_CACHE") {
+            Some(dirs) => std::env::split_paths(&dirs).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Layer the given read-only caches over `cache`, in order.
+    fn add_read_only_caches(
+        cache: &mut fetch_source::Cache,
+        dirs: Vec<PathBuf>,
+    ) -> Result<(), AppError> {
+        for dir in Self::detect_read_only_cache_dirs(dirs) {
+            cache.add_read_only(&dir).map_err(|e| {
+                AppError::arg_validation(format!(
+                    "failed to load read-only cache in {}: {}",
+                    dir.display(),
+                    e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The vendor directory to use for the given manifest, defaulting to `fetch-source-vendor` next
+    /// to the manifest
+    fn detect_vendor_dir(arg: Option<PathBuf>, manifest_file: &std::path::Path) -> PathBuf {
+        match arg {
+            Some(dir) => dir,
+            None => manifest_file
+                .parent()
+                .unwrap_or(std::path::Path::new(""))
+                .join(fetch_source::DEFAULT_VENDOR_DIR),
+        }
+    }
+
+    /// Read the vendored sources in `vendor_dir`, if anything has been vendored there
+    fn read_vendor(vendor_dir: PathBuf) -> Result<Option<fetch_source::Vendor>, AppError> {
+        if !fetch_source::Vendor::vendor_file_exists(&vendor_dir) {
+            return Ok(None);
+        }
+        fetch_source::Vendor::read(&vendor_dir)
+            .map(Some)
+            .map_err(|e| {
+                AppError::arg_validation(format!(
+                    "failed to load vendored sources in {}: {}",
+                    vendor_dir.display(),
+                    e
+                ))
+            })
+    }
+
+    /// The severity of each lint. Groups (i.e. `warnings`) are applied before individual lints so
+    /// that individual lints can override them. Within each, `--allow`, `--warn` and `--deny` are
+    /// applied in that order.
+    fn lints(
+        allow: Vec<LintSelection>,
+        warn: Vec<LintSelection>,
+        deny: Vec<LintSelection>,
+    ) -> fetch_source::Lints {
+        use fetch_source::Severity;
+        let mut lints = fetch_source::Lints::default();
+        let groups = |selections: &[LintSelection]| {
+            selections
+                .iter()
+                .any(|selection| matches!(selection, LintSelection::Warnings))
+        };
+        if groups(&allow) {
+            for lint in fetch_source::Lint::ALL {
+                if lints.severity(*lint) == Severity::Warn {
+                    lints.set(*lint, Severity::Allow);
+                }
+            }
+        }
+        if groups(&warn) {
+            for lint in fetch_source::Lint::ALL {
+                lints.set(*lint, Severity::Warn);
+            }
+        }
+        if groups(&deny) {
+            lints.deny_warnings();
+        }
+        for (selections, severity) in [
+            (allow, Severity::Allow),
+            (warn, Severity::Warn),
+            (deny, Severity::Deny),
+        ] {
+            for selection in selections {
+                if let LintSelection::Lint(lint) = selection {
+                    lints.set(lint, severity);
+                }
+            }
+        }
+        lints
+    }
+
+    /// Set the number of threads in the global thread pool
+    fn set_num_threads(threads: Option<u32>) -> Result<(), AppError> {
+        if let Some(threads) = threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads as usize)
+                .build_global()
+                .map_err(|e| {
+                    AppError::arg_validation(format!("Failed to set thread count: {e}"))
+                })?;
+        }
+        Ok(())
+    }
+
     /// Loads the cache from the given directory, creating a new cache if the file does not exist.
     /// Also creates the directory if it does not exist.
     fn load_cache_from(cache_dir: std::path::PathBuf) -> Result<fetch_source::Cache, AppError> {
@@ -248,8 +876,17 @@ impl TryFrom<Command> for ValidatedCommand {
         match command {
             Command::Fetch {
                 manifest_file,
+                packages,
+                features,
+                target,
                 out_dir,
                 cache_dir,
+                read_only_cache_dirs,
+                vendor_dir,
+                dedup,
+                link_mode,
+                sync,
+                force,
                 threads,
             } => {
                 // If given, validate that the output directory exists
@@ -268,35 +905,281 @@ impl TryFrom<Command> for ValidatedCommand {
                 };
 
                 let cache_dir = ValidatedArgs::detect_cache_dir(cache_dir)?;
-                let cache = ValidatedArgs::load_cache_from(cache_dir)?;
-
-                if let Some(threads) = threads {
-                    rayon::ThreadPoolBuilder::new()
-                        .num_threads(threads as usize)
-                        .build_global()
-                        .map_err(|e| {
-                            AppError::arg_validation(format!("Failed to set thread count: {e}"))
-                        })?;
+                let mut cache = ValidatedArgs::load_cache_from(cache_dir)?;
+                ValidatedArgs::add_read_only_caches(&mut cache, read_only_cache_dirs)?;
+
+                ValidatedArgs::set_num_threads(threads)?;
+
+                let (manifest_file, selection) =
+                    ValidatedArgs::select_sources(manifest_file, packages)?;
+                let vendor = ValidatedArgs::read_vendor(ValidatedArgs::detect_vendor_dir(
+                    vendor_dir,
+                    &manifest_file,
+                ))?;
+
+                let mut conditions = fetch_source::Conditions::new();
+                match features {
+                    Some(features) => {
+                        for feature in features.iter().filter(|f| !f.is_empty()) {
+                            conditions.enable_feature(feature);
+                        }
+                    }
+                    None => {
+                        conditions.all_features();
+                    }
+                }
+                if let Some(target) = target {
+                    conditions.target_from_rustc(&target).map_err(|e| {
+                        AppError::arg_validation(format!(
+                            "failed to get the configuration of target '{target}': {e}"
+                        ))
+                    })?;
                 }
 
                 Ok(ValidatedCommand::Fetch {
-                    manifest_file: ValidatedArgs::detect_manifest_file(manifest_file)?,
+                    manifest_file,
+                    selection,
+                    conditions,
                     out_dir,
                     cache,
+                    vendor,
+                    dedup,
+                    link_mode,
+                    sync,
+                    force,
+                })
+            }
+            Command::Vendor {
+                vendor_dir,
+                manifest_file,
+                packages,
+                cache_dir,
+                read_only_cache_dirs,
+                threads,
+            } => {
+                let cache_dir = ValidatedArgs::detect_cache_dir(cache_dir)?;
+                let mut cache = ValidatedArgs::load_cache_from(cache_dir)?;
+                ValidatedArgs::add_read_only_caches(&mut cache, read_only_cache_dirs)?;
+
+                ValidatedArgs::set_num_threads(threads)?;
+
+                let (manifest_file, selection) =
+                    ValidatedArgs::select_sources(manifest_file, packages)?;
+                let vendor_dir = ValidatedArgs::detect_vendor_dir(vendor_dir, &manifest_file);
+                if !vendor_dir.exists() {
+                    std::fs::create_dir_all(&vendor_dir)?;
+                }
+                let vendor = fetch_source::Vendor::load_or_create(&vendor_dir).map_err(|e| {
+                    AppError::arg_validation(format!(
+                        "failed to load vendored sources in {}: {}",
+                        vendor_dir.display(),
+                        e
+                    ))
+                })?;
+
+                Ok(ValidatedCommand::Vendor {
+                    manifest_file,
+                    selection,
+                    cache,
+                    vendor,
                 })
             }
             Command::List {
                 manifest_file,
+                packages,
                 format,
-            } => Ok(ValidatedCommand::List {
-                manifest_file: ValidatedArgs::detect_manifest_file(manifest_file)?,
-                format,
-            }),
+            } => {
+                let (manifest_file, selection) =
+                    ValidatedArgs::select_sources(manifest_file, packages)?;
+                Ok(ValidatedCommand::List {
+                    manifest_file,
+                    selection,
+                    format,
+                })
+            }
+            Command::Check {
+                manifest_file,
+                packages,
+                allow,
+                warn,
+                deny,
+            } => {
+                let (manifest_file, selection) =
+                    ValidatedArgs::select_sources(manifest_file, packages)?;
+                // Vendored sources are pinned, so their references don't float
+                let vendor = ValidatedArgs::read_vendor(ValidatedArgs::detect_vendor_dir(
+                    None,
+                    &manifest_file,
+                ))?;
+                Ok(ValidatedCommand::Check {
+                    manifest_file,
+                    selection,
+                    lints: ValidatedArgs::lints(allow, warn, deny),
+                    vendor,
+                })
+            }
+            Command::Add {
+                name,
+                manifest_file,
+                git,
+                branch,
+                tag,
+                rev,
+                recursive,
+                tar,
+                sha256,
+                dest,
+            } => {
+                let compute_sha256 = matches!(sha256, Some(None));
+                let source = match (git, tar) {
+                    (Some(url), _) => {
+                        let reference = if let Some(branch) = branch {
+                            Some(GitReference::Branch(branch))
+                        } else if let Some(tag) = tag {
+                            Some(GitReference::Tag(tag))
+                        } else {
+                            rev.map(GitReference::Rev)
+                        };
+                        NewSource::Git {
+                            url,
+                            reference,
+                            recursive,
+                        }
+                    }
+                    (None, Some(url)) => NewSource::Tar {
+                        url,
+                        sha256: sha256.flatten(),
+                    },
+                    (None, None) => unreachable!("--git is required unless --tar is given"),
+                };
+                Ok(ValidatedCommand::Add {
+                    manifest_file: ValidatedArgs::detect_manifest_file(manifest_file)?,
+                    name,
+                    source,
+                    compute_sha256,
+                    dest,
+                })
+            }
+            Command::Remove {
+                names,
+                manifest_file,
+                evict,
+                other_manifest,
+                cache_dir,
+            } => {
+                let eviction = if evict {
+                    let cache_dir = ValidatedArgs::detect_cache_dir(cache_dir)?;
+                    let cache = fetch_source::Cache::read(&cache_dir).map_err(|e| {
+                        AppError::arg_validation(format!(
+                            "failed to load cache in {}: {}",
+                            cache_dir.display(),
+                            e
+                        ))
+                    })?;
+                    Some(Eviction {
+                        cache,
+                        other_manifests: other_manifest,
+                    })
+                } else {
+                    None
+                };
+                Ok(ValidatedCommand::Remove {
+                    manifest_file: ValidatedArgs::detect_manifest_file(manifest_file)?,
+                    names,
+                    eviction,
+                })
+            }
+            Command::Schema { table } => Ok(ValidatedCommand::Schema { table }),
             Command::Cached {
+                action: Some(action),
+                cache_dir,
+                read_only_cache_dirs,
+                ..
+            } => {
+                let cache_dir = ValidatedArgs::detect_cache_dir(cache_dir)?;
+                match action {
+                    CachedAction::Export {
+                        bundle,
+                        manifest_file,
+                        packages,
+                        sources,
+                        all,
+                    } => {
+                        let mut cache = fetch_source::Cache::read(&cache_dir).map_err(|e| {
+                            AppError::arg_validation(format!(
+                                "failed to load cache in {}: {}",
+                                cache_dir.display(),
+                                e
+                            ))
+                        })?;
+                        ValidatedArgs::add_read_only_caches(&mut cache, read_only_cache_dirs)?;
+                        let selection = if all {
+                            ExportSelection::All
+                        } else {
+                            let (manifest_file, selection) =
+                                ValidatedArgs::select_sources(manifest_file, packages)?;
+                            ExportSelection::Manifest {
+                                manifest_file,
+                                selection,
+                                sources,
+                            }
+                        };
+                        Ok(ValidatedCommand::Export {
+                            cache,
+                            bundle,
+                            selection,
+                        })
+                    }
+                    CachedAction::Import { bundle } => {
+                        let mut cache = ValidatedArgs::load_cache_from(cache_dir)?;
+                        ValidatedArgs::add_read_only_caches(&mut cache, read_only_cache_dirs)?;
+                        Ok(ValidatedCommand::Import { cache, bundle })
+                    }
+                    CachedAction::Dedup => Ok(ValidatedCommand::Dedup {
+                        cache: fetch_source::Cache::read(&cache_dir).map_err(|e| {
+                            AppError::arg_validation(format!(
+                                "failed to load cache in {}: {}",
+                                cache_dir.display(),
+                                e
+                            ))
+                        })?,
+                    }),
+                    CachedAction::Pack { unused_for } => Ok(ValidatedCommand::Pack {
+                        cache: fetch_source::Cache::read(&cache_dir).map_err(|e| {
+                            AppError::arg_validation(format!(
+                                "failed to load cache in {}: {}",
+                                cache_dir.display(),
+                                e
+                            ))
+                        })?,
+                        unused_for: std::time::Duration::from_secs(unused_for * 24 * 60 * 60),
+                    }),
+                    CachedAction::Merge { other_cache_dir } => {
+                        let other = fetch_source::Cache::read(&other_cache_dir).map_err(|e| {
+                            AppError::arg_validation(format!(
+                                "failed to load cache in {}: {}",
+                                other_cache_dir.display(),
+                                e
+                            ))
+                        })?;
+                        let cache = ValidatedArgs::load_cache_from(cache_dir)?;
+                        if cache.cache_dir() == other.cache_dir() {
+                            return Err(AppError::arg_validation(
+                                "cannot merge a cache with itself".to_string(),
+                            ));
+                        }
+                        Ok(ValidatedCommand::Merge { cache, other })
+                    }
+                }
+            }
+            Command::Cached {
+                action: None,
                 format,
                 cache_dir: cache_dir_arg,
+                read_only_cache_dirs,
                 manifest_file,
                 source,
+                packages,
                 tar,
                 git,
                 branch,
@@ -304,22 +1187,33 @@ impl TryFrom<Command> for ValidatedCommand {
                 rev,
                 recursive,
             } => {
+                // Package selection only makes sense when querying a manifest
+                if manifest_file.is_none() && packages.is_given() {
+                    return Err(AppError::arg_validation(
+                        "--workspace, --cargo-metadata and --package require --manifest-file and \
+                         --source"
+                            .to_string(),
+                    ));
+                }
                 let cache_dir = ValidatedArgs::detect_cache_dir(cache_dir_arg)?;
                 // For the cached command, don't create the cache directory if it doesn't exist
-                let cache = fetch_source::Cache::read(&cache_dir).map_err(|e| {
+                let mut cache = fetch_source::Cache::read(&cache_dir).map_err(|e| {
                     AppError::arg_validation(format!(
                         "failed to load cache in {}: {}",
                         cache_dir.display(),
                         e
                     ))
                 })?;
+                ValidatedArgs::add_read_only_caches(&mut cache, read_only_cache_dirs)?;
                 // Validate the manifest file if given
                 let query_args: Option<CacheQuery> = if let Some(path) = manifest_file {
-                    let manifest_file = ValidatedArgs::detect_manifest_file(Some(path))?;
+                    let (manifest_file, selection) =
+                        ValidatedArgs::select_sources(Some(path), packages)?;
                     let source =
                         source.expect("--source is required when --manifest-file is given");
                     Some(CacheQuery::Manifest {
                         manifest_file,
+                        selection,
                         source,
                     })
                 } else if tar.is_some() {
@@ -355,6 +1249,10 @@ static VERSION: &str = concat!(
     ")"
 );
 
+/// The target triple this binary was built for, which `${target}` expands to unless `--target` is
+/// given
+static HOST_TARGET: &str = env!("HOST_TARGET");
+
 pub fn parse() -> Result<ValidatedArgs, AppError> {
     let raw_args = std::env::args().collect::<Vec<_>>();
     // If run via `cargo fetch-source` skip the command argument which cargo passes to the binary.
@@ -370,7 +1268,17 @@ pub fn parse() -> Result<ValidatedArgs, AppError> {
             err.format(&mut Args::command()).exit();
         }
     };
+    // `${target}` is the target being fetched for, otherwise the host this was built for
+    let mut variables = fetch_source::Variables::new();
+    match &args.command {
+        Command::Fetch {
+            target: Some(target),
+            ..
+        } => variables.target(target),
+        _ => variables.target(HOST_TARGET),
+    };
     Ok(ValidatedArgs {
         command: ValidatedCommand::try_from(args.command)?,
+        variables,
     })
 }
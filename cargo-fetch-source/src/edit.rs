@@ -0,0 +1,171 @@
+//! Edit the `fetch-source` table of a manifest, preserving its formatting and comments.
+
+use std::path::{Path, PathBuf};
+
+use fetch_source::GitReference;
+
+/// Errors which may occur while editing a manifest
+#[derive(Debug, thiserror::Error)]
+pub enum EditError {
+    #[error(transparent)]
+    Toml(#[from] toml_edit::TomlError),
+    #[error("expected a [package] or [workspace] table")]
+    NoRootTable,
+    #[error("expected '{0}' to be a table")]
+    NotATable(String),
+    #[error("source '{0}' already exists")]
+    SourceExists(String),
+    #[error("no source called '{0}'")]
+    NoSuchSource(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A source to add to a manifest
+#[derive(Debug)]
+pub enum NewSource {
+    Git {
+        url: String,
+        reference: Option<GitReference>,
+        recursive: bool,
+    },
+    Tar {
+        url: String,
+        sha256: Option<String>,
+    },
+}
+
+impl NewSource {
+    /// The source's definition as it should appear in the manifest
+    fn to_inline_table(&self, dest: Option<&Path>) -> toml_edit::InlineTable {
+        let mut table = toml_edit::InlineTable::new();
+        match self {
+            NewSource::Git {
+                url,
+                reference,
+                recursive,
+            } => {
+                table.insert("git", url.into());
+                match reference {
+                    Some(GitReference::Branch(branch)) => table.insert("branch", branch.into()),
+                    Some(GitReference::Tag(tag)) => table.insert("tag", tag.into()),
+                    Some(GitReference::Rev(rev)) => table.insert("rev", rev.into()),
+                    None => None,
+                };
+                if *recursive {
+                    table.insert("recursive", true.into());
+                }
+            }
+            NewSource::Tar { url, sha256 } => {
+                table.insert("tar", url.into());
+                if let Some(sha256) = sha256 {
+                    table.insert("sha256", sha256.into());
+                }
+            }
+        }
+        if let Some(dest) = dest {
+            table.insert("dest", dest.display().to_string().into());
+        }
+        table.fmt();
+        table
+    }
+}
+
+/// A manifest being edited
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    document: toml_edit::DocumentMut,
+}
+
+impl Manifest {
+    /// Parse the manifest at `path` from its contents
+    pub fn parse(path: &Path, contents: &str) -> Result<Self, EditError> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            document: contents.parse()?,
+        })
+    }
+
+    /// The key of the table containing `metadata.fetch-source`: `package`, or `workspace` in a
+    /// virtual manifest
+    fn root_key(&self) -> Result<&'static str, EditError> {
+        ["package", "workspace"]
+            .into_iter()
+            .find(|key| self.document.contains_key(key))
+            .ok_or(EditError::NoRootTable)
+    }
+
+    /// The `fetch-source` table, created if it doesn't exist
+    fn sources_mut(&mut self) -> Result<&mut dyn toml_edit::TableLike, EditError> {
+        let key = self.root_key()?;
+        let root = self.document[key]
+            .as_table_like_mut()
+            .ok_or_else(|| EditError::NotATable(key.to_string()))?;
+        let metadata = root
+            .entry("metadata")
+            .or_insert_with(|| {
+                // Only write the `[<root>.metadata.fetch-source]` header
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| EditError::NotATable(format!("{key}.metadata")))?;
+        metadata
+            .entry("fetch-source")
+            .or_insert_with(toml_edit::table)
+            .as_table_like_mut()
+            .ok_or_else(|| EditError::NotATable(format!("{key}.metadata.fetch-source")))
+    }
+
+    /// Whether the manifest declares a source called `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.root_key()
+            .ok()
+            .and_then(|key| self.document.get(key))
+            .and_then(|root| root.get("metadata"))
+            .and_then(|metadata| metadata.get("fetch-source"))
+            .and_then(toml_edit::Item::as_table_like)
+            .is_some_and(|sources| sources.contains_key(name))
+    }
+
+    /// Add a source, copied to `dest` if given, failing if a source with the same name exists
+    pub fn add(
+        &mut self,
+        name: &str,
+        source: &NewSource,
+        dest: Option<&Path>,
+    ) -> Result<(), EditError> {
+        let sources = self.sources_mut()?;
+        if sources.contains_key(name) {
+            return Err(EditError::SourceExists(name.to_string()));
+        }
+        sources.insert(name, source.to_inline_table(dest).into());
+        Ok(())
+    }
+
+    /// Remove a source, failing if there is no source with that name
+    pub fn remove(&mut self, name: &str) -> Result<(), EditError> {
+        let key = self.root_key()?;
+        self.document
+            .get_mut(key)
+            .and_then(|root| root.get_mut("metadata"))
+            .and_then(|metadata| metadata.get_mut("fetch-source"))
+            .and_then(toml_edit::Item::as_table_like_mut)
+            .and_then(|sources| sources.remove(name))
+            .map(|_| ())
+            .ok_or_else(|| EditError::NoSuchSource(name.to_string()))
+    }
+
+    /// Write the manifest back to its file
+    pub fn save(&self) -> Result<(), EditError> {
+        Ok(std::fs::write(&self.path, self.document.to_string())?)
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
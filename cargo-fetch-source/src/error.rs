@@ -21,6 +21,27 @@ pub enum AppErrorKind {
     CacheSave,
     /// Missing artefact directory errors
     MissingArtefact,
+    /// Bundle export or import errors
+    Bundle,
+    /// Vendor file saving errors
+    VendorSave,
+    /// Cache merging errors
+    Merge,
+    /// Cache deduplication errors
+    Dedup,
+    /// Artefact packing or unpacking errors
+    Pack,
+    /// Output directory syncing errors
+    Sync,
+    /// Manifest file editing errors
+    ManifestEdit,
+    /// Errors computing the checksum of a source
+    Checksum,
+    /// Cache eviction errors
+    Evict,
+    /// Denied lints were found by `check`. The findings are reported as they are found, so this
+    /// variant only exists to produce the correct `ExitCode`.
+    Check,
 }
 
 /// Internal error type that contains all application error variants.
@@ -36,14 +57,45 @@ pub enum AppErrorInner {
         #[source]
         err: std::io::Error,
     },
-    #[error("Failed to parse manifest file: {manifest}")]
+    #[error("Failed to parse manifest file: {err}")]
     ManifestParse {
-        manifest: String,
         #[source]
-        err: fetch_source::SourceParseError,
+        err: fetch_source::Diagnostic,
+    },
+    #[error("Failed to parse manifest file: {err}")]
+    WorkspaceParse {
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("Failed to find the workspace containing {}: {err}", manifest.display())]
+    WorkspaceFind {
+        manifest: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("Failed to read the manifests of workspace {}: {err}", manifest.display())]
+    WorkspaceRead {
+        manifest: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("Failed to edit manifest file {}: {err}", manifest.display())]
+    ManifestEdit {
+        manifest: std::path::PathBuf,
+        #[source]
+        err: crate::edit::EditError,
     },
     #[error("Failed to fetch one or more source(s)")]
     Fetch,
+    #[error("failed to compute the checksum of '{url}': {reason}")]
+    ChecksumFailed {
+        url: String,
+        reason: String,
+        #[source]
+        err: fetch_source::FetchError,
+    },
+    #[error("check failed: {errors} error(s) found")]
+    Check { errors: usize },
     #[error("failed to copy {} to {}", src.display(), dst.display())]
     CopyArtefactFailed {
         src: std::path::PathBuf,
@@ -57,11 +109,60 @@ pub enum AppErrorInner {
         #[source]
         err: fetch_source::Error,
     },
+    #[error("failed to save vendored sources to {}", path.display())]
+    VendorSaveFailed {
+        path: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
     #[error("expected directory for '{}' to exist at {}", name, path.display())]
     MissingArtefactDirectory {
         name: String,
         path: std::path::PathBuf,
     },
+    #[error("failed to {action} bundle {}: {err}", path.display())]
+    BundleFailed {
+        action: &'static str,
+        path: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("failed to merge cache {}: {err}", path.display())]
+    MergeFailed {
+        path: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("failed to deduplicate cache {}: {err}", path.display())]
+    DedupFailed {
+        path: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("failed to pack unused sources in cache {}: {err}", path.display())]
+    PackFailed {
+        path: std::path::PathBuf,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("failed to evict '{name}' from the cache: {err}")]
+    EvictFailed {
+        name: String,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("failed to unpack cached source '{name}': {err}")]
+    UnpackFailed {
+        name: String,
+        #[source]
+        err: fetch_source::Error,
+    },
+    #[error("failed to sync '{name}': {err}")]
+    SyncFailed {
+        name: String,
+        #[source]
+        err: crate::sync::SyncError,
+    },
     #[error("no source called '{name}' in manifest {manifest:?}")]
     NoSuchSource {
         name: String,
@@ -120,19 +221,93 @@ impl AppError {
         )
     }
 
-    /// Create a manifest parse error
-    pub fn manifest_parse(manifest: String, err: fetch_source::SourceParseError) -> Self {
+    /// Create a manifest parse error, locating the error in `document` (the contents of
+    /// `manifest`) if given
+    pub fn manifest_parse(
+        manifest: &std::path::Path,
+        err: fetch_source::SourceParseError,
+        document: Option<&str>,
+    ) -> Self {
         Self::new(
-            AppErrorInner::ManifestParse { manifest, err },
+            AppErrorInner::ManifestParse {
+                err: fetch_source::Diagnostic::new(err, manifest, document),
+            },
             AppErrorKind::ManifestParse,
         )
     }
 
+    /// As [`AppError::manifest_parse`], reading the manifest to locate the error
+    pub fn manifest_parse_in_file(
+        manifest: &std::path::Path,
+        err: fetch_source::SourceParseError,
+    ) -> Self {
+        let document = std::fs::read_to_string(manifest).ok();
+        Self::manifest_parse(manifest, err, document.as_deref())
+    }
+
+    /// Create an error for a failure to find the workspace containing `manifest`
+    pub fn workspace_find(manifest: &std::path::Path, err: fetch_source::Error) -> Self {
+        Self::new(
+            AppErrorInner::WorkspaceFind {
+                manifest: manifest.to_path_buf(),
+                err,
+            },
+            AppErrorKind::ManifestRead,
+        )
+    }
+
+    /// Create an error for the sources of the workspace whose root manifest is `manifest`
+    pub fn workspace_sources(manifest: &std::path::Path, err: fetch_source::Error) -> Self {
+        if *err.kind() == fetch_source::ErrorKind::Io {
+            Self::new(
+                AppErrorInner::WorkspaceRead {
+                    manifest: manifest.to_path_buf(),
+                    err,
+                },
+                AppErrorKind::ManifestRead,
+            )
+        } else {
+            // Parse errors are located in the manifest which contains them
+            Self::new(
+                AppErrorInner::WorkspaceParse { err },
+                AppErrorKind::ManifestParse,
+            )
+        }
+    }
+
+    /// Create a manifest edit error
+    pub fn manifest_edit(manifest: &std::path::Path, err: crate::edit::EditError) -> Self {
+        Self::new(
+            AppErrorInner::ManifestEdit {
+                manifest: manifest.to_path_buf(),
+                err,
+            },
+            AppErrorKind::ManifestEdit,
+        )
+    }
+
+    /// Create a checksum error for the archive at `url`
+    pub fn checksum_failed(url: String, err: fetch_source::FetchError) -> Self {
+        // The fetch error itself only says that fetching failed, so report its cause
+        let reason = std::error::Error::source(&err)
+            .map(|cause| cause.to_string())
+            .unwrap_or_else(|| err.to_string());
+        Self::new(
+            AppErrorInner::ChecksumFailed { url, reason, err },
+            AppErrorKind::Checksum,
+        )
+    }
+
     /// Create a fetch error
     pub fn fetch() -> Self {
         Self::new(AppErrorInner::Fetch, AppErrorKind::Fetch)
     }
 
+    /// Create a check error for the given number of denied findings
+    pub fn check(errors: usize) -> Self {
+        Self::new(AppErrorInner::Check { errors }, AppErrorKind::Check)
+    }
+
     /// Create a copy artefact failed error
     pub fn copy_artefact_failed(
         src: std::path::PathBuf,
@@ -153,6 +328,14 @@ impl AppError {
         )
     }
 
+    /// Create a vendor save failed error
+    pub fn vendor_save_failed(path: std::path::PathBuf, err: fetch_source::Error) -> Self {
+        Self::new(
+            AppErrorInner::VendorSaveFailed { path, err },
+            AppErrorKind::VendorSave,
+        )
+    }
+
     /// Create a missing artefact directory error
     pub fn missing_artefact_directory(name: String, path: std::path::PathBuf) -> Self {
         Self::new(
@@ -161,6 +344,78 @@ impl AppError {
         )
     }
 
+    /// Create a bundle export error
+    pub fn bundle_export_failed<E>(path: std::path::PathBuf, err: E) -> Self
+    where
+        fetch_source::Error: From<E>,
+    {
+        Self::new(
+            AppErrorInner::BundleFailed {
+                action: "export",
+                path,
+                err: err.into(),
+            },
+            AppErrorKind::Bundle,
+        )
+    }
+
+    /// Create a bundle import error
+    pub fn bundle_import_failed<E>(path: std::path::PathBuf, err: E) -> Self
+    where
+        fetch_source::Error: From<E>,
+    {
+        Self::new(
+            AppErrorInner::BundleFailed {
+                action: "import",
+                path,
+                err: err.into(),
+            },
+            AppErrorKind::Bundle,
+        )
+    }
+
+    /// Create a cache merge failed error
+    pub fn merge_failed(path: std::path::PathBuf, err: fetch_source::Error) -> Self {
+        Self::new(
+            AppErrorInner::MergeFailed { path, err },
+            AppErrorKind::Merge,
+        )
+    }
+
+    /// Create a cache deduplication failed error
+    pub fn dedup_failed(path: std::path::PathBuf, err: fetch_source::Error) -> Self {
+        Self::new(
+            AppErrorInner::DedupFailed { path, err },
+            AppErrorKind::Dedup,
+        )
+    }
+
+    /// Create a pack failed error
+    pub fn pack_failed(path: std::path::PathBuf, err: fetch_source::Error) -> Self {
+        Self::new(AppErrorInner::PackFailed { path, err }, AppErrorKind::Pack)
+    }
+
+    /// Create an unpack failed error
+    pub fn unpack_failed(name: String, err: fetch_source::Error) -> Self {
+        Self::new(
+            AppErrorInner::UnpackFailed { name, err },
+            AppErrorKind::Pack,
+        )
+    }
+
+    /// Create an evict failed error
+    pub fn evict_failed(name: String, err: fetch_source::Error) -> Self {
+        Self::new(
+            AppErrorInner::EvictFailed { name, err },
+            AppErrorKind::Evict,
+        )
+    }
+
+    /// Create a sync failed error
+    pub fn sync_failed(name: String, err: crate::sync::SyncError) -> Self {
+        Self::new(AppErrorInner::SyncFailed { name, err }, AppErrorKind::Sync)
+    }
+
     /// Create a no such source error
     pub fn no_such_source(name: String, manifest: std::path::PathBuf) -> Self {
         Self::new(
@@ -179,7 +434,7 @@ impl From<std::io::Error> for AppError {
 impl From<AppError> for ExitCode {
     fn from(error: AppError) -> Self {
         ExitCode::from(match error.error_kind() {
-            AppErrorKind::Fetch => 1,
+            AppErrorKind::Fetch | AppErrorKind::Check => 1,
             AppErrorKind::ArgValidation => 2,
             _ => 3,
         })
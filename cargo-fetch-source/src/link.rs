@@ -0,0 +1,138 @@
+//! Materialise cached artefacts in the output directory by copying or linking them.
+
+use std::path::Path;
+
+/// How an artefact is materialised in the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkMode {
+    /// Copy every file
+    #[default]
+    Copy,
+    /// Hard-link every file to the cache. Editing a linked file also edits the cached copy.
+    Hardlink,
+    /// Share the contents of every file with the cache using copy-on-write, where the filesystem
+    /// supports it
+    Reflink,
+    /// Symlink the source's directory to the cache
+    Symlink,
+}
+
+impl std::fmt::Display for LinkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkMode::Copy => write!(f, "copy"),
+            LinkMode::Hardlink => write!(f, "hardlink"),
+            LinkMode::Reflink => write!(f, "reflink"),
+            LinkMode::Symlink => write!(f, "symlink"),
+        }
+    }
+}
+
+/// Materialise the artefact at `from` in `to` using `mode`. Where `mode` isn't supported (e.g.
+/// hard links across filesystems) fall back to copying. Return the mode actually used, which is
+/// [`LinkMode::Copy`] if any file had to be copied.
+pub fn link_artefact(mode: LinkMode, from: &Path, to: &Path) -> std::io::Result<LinkMode> {
+    match mode {
+        LinkMode::Copy => {
+            dircpy::copy_dir(from, to)?;
+            Ok(LinkMode::Copy)
+        }
+        LinkMode::Symlink => link_dir(from, to),
+        LinkMode::Hardlink | LinkMode::Reflink => {
+            let mut used = mode;
+            link_files(mode, from, to, &mut used)?;
+            Ok(used)
+        }
+    }
+}
+
+/// Replace `to` with a symlink to `from`. An existing directory at `to` is never removed.
+fn link_dir(from: &Path, to: &Path) -> std::io::Result<LinkMode> {
+    if to.is_symlink() {
+        remove_symlink(to)?;
+    } else if to.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a symlink", to.display()),
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match symlink_dir(from, to) {
+        Ok(()) => Ok(LinkMode::Symlink),
+        Err(_) => {
+            dircpy::copy_dir(from, to)?;
+            Ok(LinkMode::Copy)
+        }
+    }
+}
+
+/// Recursively link each file in `from` into `to`, replacing existing files. Symlinks are
+/// recreated rather than followed.
+fn link_files(mode: LinkMode, from: &Path, to: &Path, used: &mut LinkMode) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dest = to.join(entry.file_name());
+        if file_type.is_dir() {
+            link_files(mode, &entry.path(), &dest, used)?;
+            continue;
+        }
+        if dest.is_symlink() || dest.is_file() {
+            std::fs::remove_file(&dest)?;
+        }
+        if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &dest)?;
+        } else if !link_file(mode, &entry.path(), &dest) {
+            std::fs::copy(entry.path(), &dest)?;
+            *used = LinkMode::Copy;
+        }
+    }
+    Ok(())
+}
+
+/// Try to link a single file, returning whether it succeeded
+fn link_file(mode: LinkMode, from: &Path, to: &Path) -> bool {
+    match mode {
+        LinkMode::Hardlink => std::fs::hard_link(from, to).is_ok(),
+        LinkMode::Reflink => reflink_copy::reflink(from, to).is_ok(),
+        LinkMode::Copy | LinkMode::Symlink => false,
+    }
+}
+
+#[cfg(unix)]
+fn symlink_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(from, to)
+}
+
+#[cfg(windows)]
+fn symlink_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(from, to)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink_dir(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(windows)]
+fn remove_symlink(path: &Path) -> std::io::Result<()> {
+    std::fs::remove_dir(path).or_else(|_| std::fs::remove_file(path))
+}
+
+#[cfg(not(windows))]
+fn remove_symlink(path: &Path) -> std::io::Result<()> {
+    std::fs::remove_file(path)
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::copy(from, to).map(|_| ())
+}
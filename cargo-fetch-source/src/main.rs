@@ -6,8 +6,11 @@ use fetch_source::{Source, SourcesTable};
 use std::error::Error;
 
 mod args;
+mod edit;
 mod error;
 mod fetch;
+mod link;
+mod sync;
 
 use args::OutputFormat;
 
@@ -24,28 +27,131 @@ fn main() -> std::process::ExitCode {
     }
 }
 
-fn sources(manifest_file: &std::path::Path) -> Result<fetch_source::SourcesTable, error::AppError> {
+fn sources(
+    manifest_file: &std::path::Path,
+    selection: &args::SourceSelection,
+    variables: &fetch_source::Variables,
+) -> Result<fetch_source::SourcesTable, error::AppError> {
+    selected_sources_with_options(manifest_file, selection, variables).map(|(sources, _)| sources)
+}
+
+/// Parse the sources of the selected manifests along with their options
+fn selected_sources_with_options(
+    manifest_file: &std::path::Path,
+    selection: &args::SourceSelection,
+    variables: &fetch_source::Variables,
+) -> Result<(SourcesTable, fetch_source::SourceOptionsTable), error::AppError> {
+    match selection {
+        args::SourceSelection::Manifest => sources_with_options(manifest_file, variables),
+        args::SourceSelection::Workspace(workspace) => workspace
+            .load_sources(variables)
+            .map_err(|err| AppError::workspace_sources(workspace.root_manifest(), err)),
+        args::SourceSelection::CargoMetadata {
+            metadata,
+            packages,
+            include_workspace,
+        } => cargo_metadata_sources_with_options(metadata, packages, *include_workspace, variables),
+    }
+}
+
+/// Parse the sources in the manifest along with their options
+fn sources_with_options(
+    manifest_file: &std::path::Path,
+    variables: &fetch_source::Variables,
+) -> Result<(SourcesTable, fetch_source::SourceOptionsTable), error::AppError> {
     let document = std::fs::read_to_string(manifest_file)
         .map_err(|err| AppError::manifest_read(format!("{}", manifest_file.display()), err))?;
 
-    fetch_source::try_parse_toml(&document)
-        .map_err(|err| AppError::manifest_parse(format!("{}", manifest_file.display()), err))
+    fetch_source::try_parse_toml_with_variables(&document, variables)
+        .map_err(|err| AppError::manifest_parse(manifest_file, err, Some(&document)))
+}
+
+/// The manifests which may declare the selected sources
+fn selected_manifests(
+    manifest_file: &std::path::Path,
+    selection: &args::SourceSelection,
+) -> Vec<std::path::PathBuf> {
+    match selection {
+        args::SourceSelection::Manifest => vec![manifest_file.to_path_buf()],
+        args::SourceSelection::Workspace(workspace) => {
+            std::iter::once(workspace.root_manifest().to_path_buf())
+                .chain(workspace.member_manifests().iter().cloned())
+                .collect()
+        }
+        args::SourceSelection::CargoMetadata {
+            metadata,
+            packages,
+            include_workspace,
+        } => include_workspace
+            .then(|| metadata.workspace_root().join("Cargo.toml"))
+            .into_iter()
+            .chain(packages.iter().map(|p| p.manifest_path().to_path_buf()))
+            .collect(),
+    }
+}
+
+/// Parse the sources of the packages selected with `cargo metadata` along with their options
+fn cargo_metadata_sources_with_options(
+    metadata: &fetch_source::CargoMetadata,
+    packages: &[fetch_source::CargoPackage],
+    include_workspace: bool,
+    variables: &fetch_source::Variables,
+) -> Result<(SourcesTable, fetch_source::SourceOptionsTable), error::AppError> {
+    let root_manifest = metadata.workspace_root().join("Cargo.toml");
+    let mut tables = Vec::new();
+    if include_workspace {
+        tables.extend(
+            metadata
+                .workspace_sources(variables)
+                .map_err(|err| AppError::manifest_parse_in_file(&root_manifest, err))?,
+        );
+    }
+    for package in packages {
+        tables.extend(
+            package
+                .sources(variables)
+                .map_err(|err| AppError::manifest_parse_in_file(package.manifest_path(), err))?,
+        );
+    }
+    fetch_source::union_sources(tables)
+        .map_err(|err| AppError::manifest_parse_in_file(&root_manifest, err))
 }
 
 fn run() -> Result<(), error::AppError> {
     let args = args::parse()?;
+    let variables = &args.variables;
 
     match args.command {
         args::ValidatedCommand::Fetch {
             out_dir,
             manifest_file,
+            selection,
+            conditions,
             mut cache,
+            vendor,
+            dedup,
+            link_mode,
+            sync,
+            force,
         } => {
-            let cache_dir = cache.cache_dir();
-            let cache_items = cache.items_mut();
-            let sources = sources(&manifest_file)?;
+            let (mut sources, mut options) =
+                selected_sources_with_options(&manifest_file, &selection, variables)?;
+            conditions.retain(&mut sources, &mut options);
             let num_sources = sources.len();
-            let (artefacts, errors) = fetch_and_cache_sources(sources, cache_items, &cache_dir);
+            // Prefer vendored copies over the cache
+            let (vendored, sources) = match vendor {
+                Some(vendor) => vendor.partition(sources),
+                None => (Vec::new(), sources),
+            };
+            for (name, path) in &vendored {
+                println!("vendored '{name}' at '{}'", path.display());
+            }
+            let missing = sources
+                .values()
+                .filter(|s| !cache.contains(s))
+                .cloned()
+                .collect::<Vec<_>>();
+            let (artefacts, errors) = fetch_and_cache_sources(sources, &mut cache)?;
             for (name, artefact) in &artefacts {
                 println!("cached '{name} at '{}'", artefact.display());
             }
@@ -53,8 +159,27 @@ fn run() -> Result<(), error::AppError> {
                 AppError::cache_save_failed(cache.cache_file().to_path_buf(), err)
             })?;
 
+            if dedup {
+                // Only the newly-fetched sources need deduplicating
+                let mut stats = fetch_source::DedupStats::default();
+                for source in missing.iter().filter(|s| cache.items().contains(s)) {
+                    stats += cache.deduplicate(source).map_err(|err| {
+                        AppError::dedup_failed(cache.cache_dir().to_path_buf(), err)
+                    })?;
+                }
+                report_dedup_stats(stats);
+            }
+
             if let Some(out_dir) = out_dir {
-                copy_all_artefacts(&out_dir, artefacts)?;
+                let artefacts = artefacts
+                    .into_iter()
+                    .map(|(name, artefact_path)| (name, artefact_path.to_path_buf()));
+                let artefacts = vendored.into_iter().chain(artefacts);
+                if sync {
+                    sync_all_artefacts(&out_dir, artefacts, &options, force)?;
+                } else {
+                    copy_all_artefacts(&out_dir, artefacts, &options, link_mode)?;
+                }
             }
 
             // Report errors and return error status if any occurred
@@ -65,64 +190,512 @@ fn run() -> Result<(), error::AppError> {
                 Err(AppError::fetch())
             }
         }
+        args::ValidatedCommand::Vendor {
+            manifest_file,
+            selection,
+            mut cache,
+            mut vendor,
+        } => {
+            let sources = sources(&manifest_file, &selection, variables)?;
+            let num_sources = sources.len();
+            remove_stale_vendored(&mut vendor, &sources)?;
+            // Only fetch and copy sources which aren't already vendored
+            let (_, missing) = vendor.partition(sources.clone());
+            let (artefacts, errors) = fetch_and_cache_sources(missing, &mut cache)?;
+            cache.save().map_err(|err| {
+                AppError::cache_save_failed(cache.cache_file().to_path_buf(), err)
+            })?;
+
+            for (name, artefact_path) in artefacts {
+                let dest = vendor
+                    .vendor_dir()
+                    .join(fetch_source::Vendor::relative_path(&name));
+                if dest.exists() {
+                    std::fs::remove_dir_all(&dest)?;
+                }
+                copy_artefact(
+                    name.clone(),
+                    &*artefact_path,
+                    dest.clone(),
+                    link::LinkMode::Copy,
+                )?;
+                println!("vendored '{name}' at '{}'", dest.display());
+                // SAFETY: the names of the fetched artefacts come from `sources`
+                vendor.insert(name.clone(), sources[&name].clone());
+            }
+            vendor.save().map_err(|err| {
+                AppError::vendor_save_failed(vendor.vendor_file().to_path_buf(), err)
+            })?;
+
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                report_fetch_results(errors, num_sources);
+                Err(AppError::fetch())
+            }
+        }
         args::ValidatedCommand::List {
             format,
             manifest_file,
-        } => list(sources(&manifest_file)?, format),
+            selection,
+        } => list(sources(&manifest_file, &selection, variables)?, format),
+        args::ValidatedCommand::Check {
+            manifest_file,
+            selection,
+            lints,
+            vendor,
+        } => check(
+            &manifest_file,
+            &selection,
+            &lints,
+            vendor.as_ref(),
+            variables,
+        ),
+        args::ValidatedCommand::Add {
+            manifest_file,
+            name,
+            mut source,
+            compute_sha256,
+            dest,
+        } => {
+            let document = std::fs::read_to_string(&manifest_file).map_err(|err| {
+                AppError::manifest_read(format!("{}", manifest_file.display()), err)
+            })?;
+            let mut manifest = edit::Manifest::parse(&manifest_file, &document)
+                .map_err(|err| AppError::manifest_edit(&manifest_file, err))?;
+            // Don't download an archive only to reject its name
+            if manifest.contains(&name) {
+                return Err(AppError::manifest_edit(
+                    &manifest_file,
+                    edit::EditError::SourceExists(name),
+                ));
+            }
+            if compute_sha256 && let edit::NewSource::Tar { url, sha256 } = &mut source {
+                let digest = fetch_source::Tar::new(&*url)
+                    .download_sha256()
+                    .map_err(|err| AppError::checksum_failed(url.clone(), err))?;
+                println!("computed sha256 of '{url}': {digest}");
+                *sha256 = Some(digest);
+            }
+            manifest
+                .add(&name, &source, dest.as_deref())
+                .map_err(|err| AppError::manifest_edit(&manifest_file, err))?;
+            // Only write the manifest if the new source is valid
+            let edited = manifest.to_string();
+            let _ = declared_sources(&edited, variables)
+                .map_err(|err| AppError::manifest_parse(&manifest_file, err, Some(&edited)))?;
+            manifest
+                .save()
+                .map_err(|err| AppError::manifest_edit(&manifest_file, err))?;
+            println!("added '{name}' to '{}'", manifest_file.display());
+            Ok(())
+        }
+        args::ValidatedCommand::Remove {
+            manifest_file,
+            names,
+            eviction,
+        } => remove(&manifest_file, &names, eviction, variables),
+        args::ValidatedCommand::Schema { table } => {
+            let schema = if table {
+                fetch_source::table_schema()
+            } else {
+                fetch_source::manifest_schema()
+            };
+            // SAFETY: unwrap here because a schema is always valid JSON
+            println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+            Ok(())
+        }
         args::ValidatedCommand::Cached {
             format,
-            ref cache,
+            mut cache,
             query_args,
-        } => cached(cache, format, query_args),
+        } => cached(&mut cache, format, query_args, variables),
+        args::ValidatedCommand::Export {
+            mut cache,
+            bundle,
+            selection,
+        } => export(&mut cache, bundle, selection, variables),
+        args::ValidatedCommand::Pack { cache, unused_for } => {
+            let packed = cache
+                .pack_unused(unused_for)
+                .map_err(|err| AppError::pack_failed(cache.cache_dir().to_path_buf(), err))?;
+            println!("packed {packed} unused source(s)");
+            Ok(())
+        }
+        args::ValidatedCommand::Import { mut cache, bundle } => import(&mut cache, bundle),
+        args::ValidatedCommand::Dedup { cache } => {
+            let stats = cache
+                .deduplicate_all()
+                .map_err(|err| AppError::dedup_failed(cache.cache_dir().to_path_buf(), err))?;
+            report_dedup_stats(stats);
+            Ok(())
+        }
+        args::ValidatedCommand::Merge { mut cache, other } => {
+            let other_dir = other.cache_dir();
+            let merged = cache
+                .merge(other)
+                .map_err(|err| AppError::merge_failed(other_dir.to_path_buf(), err));
+            // Save whatever was merged, even if an error occurred part-way through
+            cache.save().map_err(|err| {
+                AppError::cache_save_failed(cache.cache_file().to_path_buf(), err)
+            })?;
+            println!(
+                "merged {} source(s) from '{}'",
+                merged?.len(),
+                other_dir.display()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Remove the named sources from the manifest, and from the cache if given an eviction
+fn remove(
+    manifest_file: &std::path::Path,
+    names: &[String],
+    eviction: Option<args::Eviction>,
+    variables: &fetch_source::Variables,
+) -> Result<(), AppError> {
+    let document = std::fs::read_to_string(manifest_file)
+        .map_err(|err| AppError::manifest_read(format!("{}", manifest_file.display()), err))?;
+    let mut manifest = edit::Manifest::parse(manifest_file, &document)
+        .map_err(|err| AppError::manifest_edit(manifest_file, err))?;
+    for name in names {
+        manifest
+            .remove(name)
+            .map_err(|err| AppError::manifest_edit(manifest_file, err))?;
+    }
+    // Work out what to evict before changing anything, in case a manifest can't be parsed
+    let evicted = match eviction {
+        Some(args::Eviction {
+            cache,
+            other_manifests,
+        }) => {
+            let removed = declared_sources(&document, variables)
+                .map_err(|err| AppError::manifest_parse(manifest_file, err, Some(&document)))?
+                .filter(|(name, _)| names.contains(name))
+                .collect::<Vec<_>>();
+            let kept = kept_sources(
+                manifest_file,
+                &manifest.to_string(),
+                &other_manifests,
+                variables,
+            )?;
+            let unused = removed
+                .into_iter()
+                .filter(|(_, source)| !kept.contains(&Source::digest(source)))
+                .collect::<Vec<_>>();
+            Some((cache, unused))
+        }
+        None => None,
+    };
+    manifest
+        .save()
+        .map_err(|err| AppError::manifest_edit(manifest_file, err))?;
+    for name in names {
+        println!("removed '{name}' from '{}'", manifest_file.display());
+    }
+    if let Some((mut cache, unused)) = evicted {
+        let mut result = Ok(());
+        for (name, source) in unused {
+            match cache.evict(&source) {
+                Ok(Some(_)) => println!("evicted '{name}' from the cache"),
+                Ok(None) => {}
+                Err(err) => {
+                    result = Err(AppError::evict_failed(name, err));
+                    break;
+                }
+            }
+        }
+        // Record the sources which were evicted even if a later one failed
+        cache
+            .save()
+            .map_err(|err| AppError::cache_save_failed(cache.cache_file().to_path_buf(), err))?;
+        result?;
+    }
+    Ok(())
+}
+
+/// The sources declared in a manifest's `package` and `workspace` metadata tables
+fn declared_sources(
+    document: &str,
+    variables: &fetch_source::Variables,
+) -> Result<impl Iterator<Item = (String, Source)>, fetch_source::SourceParseError> {
+    let ignore_missing = |result| match result {
+        Err(fetch_source::SourceParseError::SourceTableNotFound) => Ok(SourcesTable::new()),
+        result => result,
+    };
+    let package = ignore_missing(
+        fetch_source::try_parse_toml_with_variables(document, variables)
+            .map(|(sources, _)| sources),
+    )?;
+    let workspace = ignore_missing(
+        fetch_source::try_parse_workspace_toml_with_variables(document, variables)
+            .map(|(sources, _)| sources),
+    )?;
+    Ok(package.into_iter().chain(workspace))
+}
+
+/// The digests of the sources declared in the edited manifest, the other manifests in its
+/// workspace, and `other_manifests`
+fn kept_sources(
+    manifest_file: &std::path::Path,
+    edited: &str,
+    other_manifests: &[std::path::PathBuf],
+    variables: &fetch_source::Variables,
+) -> Result<std::collections::BTreeSet<fetch_source::Digest>, AppError> {
+    let mut manifests = other_manifests.to_vec();
+    let workspace = fetch_source::Workspace::try_find(std::path::absolute(manifest_file)?)
+        .map_err(|err| AppError::workspace_find(manifest_file, err))?;
+    if let Some(workspace) = workspace {
+        manifests.push(workspace.root_manifest().to_path_buf());
+        manifests.extend(workspace.member_manifests().iter().cloned());
+    }
+    let this = manifest_file.canonicalize()?;
+    let mut kept = declared_sources(edited, variables)
+        .map_err(|err| AppError::manifest_parse(manifest_file, err, Some(edited)))?
+        .map(|(_, source)| Source::digest(source))
+        .collect::<std::collections::BTreeSet<_>>();
+    for other in manifests {
+        // The edited manifest hasn't been saved yet
+        if other.canonicalize().is_ok_and(|other| other == this) {
+            continue;
+        }
+        let document = std::fs::read_to_string(&other)
+            .map_err(|err| AppError::manifest_read(format!("{}", other.display()), err))?;
+        kept.extend(
+            declared_sources(&document, variables)
+                .map_err(|err| AppError::manifest_parse(&other, err, Some(&document)))?
+                .map(|(_, source)| Source::digest(source)),
+        );
     }
+    Ok(kept)
+}
+
+/// Export the selected sources from the cache into a bundle
+fn export(
+    cache: &mut fetch_source::Cache,
+    bundle: std::path::PathBuf,
+    selection: args::ExportSelection,
+    variables: &fetch_source::Variables,
+) -> Result<(), AppError> {
+    let selected = match selection {
+        args::ExportSelection::All => cache
+            .items()
+            .values()
+            .map(|artefact| artefact.source().clone())
+            .collect::<Vec<_>>(),
+        args::ExportSelection::Manifest {
+            manifest_file,
+            selection,
+            sources: names,
+        } => {
+            let sources = sources(&manifest_file, &selection, variables)?;
+            if names.is_empty() {
+                sources.into_values().collect()
+            } else {
+                names
+                    .into_iter()
+                    .map(|name| match sources.get(&name) {
+                        Some(source) => Ok(source.clone()),
+                        None => Err(AppError::no_such_source(name, manifest_file.clone())),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        }
+    };
+    // Write to a sibling file and rename it into place, so a failed export leaves no partial bundle
+    let mut partial = bundle.clone().into_os_string();
+    partial.push(".partial");
+    let partial = std::path::PathBuf::from(partial);
+    let result = std::fs::File::create(&partial)
+        .map_err(fetch_source::Error::from)
+        .and_then(|file| {
+            let mut writer = std::io::BufWriter::new(file);
+            let count = fetch_source::export_bundle(cache, &selected, &mut writer)?;
+            writer.into_inner().map_err(|err| err.into_error())?;
+            std::fs::rename(&partial, &bundle)?;
+            Ok(count)
+        });
+    let count = result.map_err(|err| {
+        let _ = std::fs::remove_file(&partial);
+        AppError::bundle_export_failed(bundle.clone(), err)
+    })?;
+    // Record that the exported artefacts were used, and any which were unpacked
+    cache
+        .save()
+        .map_err(|err| AppError::cache_save_failed(cache.cache_file().to_path_buf(), err))?;
+    println!("exported {count} source(s) to '{}'", bundle.display());
+    Ok(())
 }
 
+/// Import the sources in a bundle into the cache and save it
+fn import(cache: &mut fetch_source::Cache, bundle: std::path::PathBuf) -> Result<(), AppError> {
+    let file = std::fs::File::open(&bundle)
+        .map_err(|err| AppError::bundle_import_failed(bundle.clone(), err))?;
+    let count = fetch_source::import_bundle(cache, std::io::BufReader::new(file))
+        .map_err(|err| AppError::bundle_import_failed(bundle.clone(), err))?;
+    cache
+        .save()
+        .map_err(|err| AppError::cache_save_failed(cache.cache_file().to_path_buf(), err))?;
+    println!("imported {count} source(s) from '{}'", bundle.display());
+    Ok(())
+}
+
+/// The cached artefacts of the sources which were fetched, and errors for those which weren't
+type FetchOutcome = (
+    Vec<(String, fetch_source::CacheDir)>,
+    Vec<fetch_source::FetchError>,
+);
+
 // Fetch missing sources and return all the now-cached sources, and errors for those which couldn't
-// be fetched
+// be fetched. Sources found in a read-only cache are not fetched. Packed artefacts are unpacked.
 fn fetch_and_cache_sources(
     sources: SourcesTable,
-    cache_items: &mut fetch_source::CacheItems,
-    cache_root: &fetch_source::CacheRoot,
-) -> (
-    Vec<(String, fetch_source::CacheDir)>,
-    Vec<fetch_source::FetchError>,
-) {
-    let (cached, missing) = sources
+    cache: &mut fetch_source::Cache,
+) -> Result<FetchOutcome, AppError> {
+    let (cached, missing): (SourcesTable, SourcesTable) =
+        sources.into_iter().partition(|(_, s)| cache.contains(s));
+
+    // Drop the source values of the cached sources as they are already contained in the cached
+    // artefacts. Instead, give the path to the cached artefacts
+    let cached = cached
         .into_iter()
-        .partition(|(_, s)| cache_items.contains(s));
-    let (mut fetched, errors) = fetch_all_parallel(missing, cache_items, cache_root);
-
-    // Combine the newly-fetched with the previously-cached artefacts. Drop the source values as
-    // they are now contained in the cached artefacts. Instead, give the path to the cached
-    // artefacts
-    fetched.extend(
-        cached
-            .into_iter()
-            .map(|(name, source)| (name, cache_root.append(cache_items.relative_path(&source)))),
-    );
+        .filter_map(|(name, source)| match cache.access(&source) {
+            Ok(path) => path.map(|path| Ok((name, path))),
+            Err(err) => Some(Err(AppError::unpack_failed(name, err))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Fetch sources declared more than once under different names only once
+    let mut first_names = std::collections::BTreeMap::<fetch_source::Digest, String>::new();
+    let mut duplicates = Vec::new();
+    let missing = missing
+        .into_iter()
+        .filter(
+            |(name, source)| match first_names.entry(Source::digest(source)) {
+                std::collections::btree_map::Entry::Occupied(first) => {
+                    duplicates.push((name.clone(), first.get().clone()));
+                    false
+                }
+                std::collections::btree_map::Entry::Vacant(entry) => {
+                    entry.insert(name.clone());
+                    true
+                }
+            },
+        )
+        .collect::<SourcesTable>();
 
-    (fetched, errors)
+    let cache_root = cache.cache_dir();
+    let (mut fetched, errors) = fetch_all_parallel(missing, cache.items_mut(), &cache_root);
+
+    for (name, first_name) in duplicates {
+        if let Some((_, path)) = fetched.iter().find(|(fetched, _)| *fetched == first_name) {
+            fetched.push((name, path.clone()));
+        }
+    }
+
+    // Combine the newly-fetched with the previously-cached artefacts
+    fetched.extend(cached);
+
+    Ok((fetched, errors))
 }
 
-/// Copy artefacts into their source's directory in `out_dir`
-fn copy_all_artefacts<P>(
+/// The destination of a source relative to the output directory
+fn destination(options: &fetch_source::SourceOptionsTable, name: &str) -> std::path::PathBuf {
+    match options.get(name) {
+        Some(options) => options.destination(name),
+        None => Source::as_path_component(name),
+    }
+}
+
+/// Copy or link artefacts into their source's destination in `out_dir`
+fn copy_all_artefacts<P, I>(
     out_dir: P,
-    artefacts: Vec<(String, fetch_source::CacheDir)>,
+    artefacts: I,
+    options: &fetch_source::SourceOptionsTable,
+    link_mode: link::LinkMode,
 ) -> Result<(), AppError>
 where
     P: AsRef<std::path::Path>,
+    I: IntoIterator<Item = (String, std::path::PathBuf)>,
 {
     for (name, artefact_path) in artefacts {
-        copy_artefact(&out_dir, name, &*artefact_path)?;
+        let dest = out_dir.as_ref().join(destination(options, &name));
+        copy_artefact(name, artefact_path, dest, link_mode)?;
     }
     Ok(())
 }
 
-/// Copy an artefact from its cache dir into the source's directory within `out_dir`
-fn copy_artefact<P, Q>(out_dir: P, name: String, artefact_path: Q) -> Result<(), AppError>
+/// Sync artefacts into their source's destination in `out_dir`, recording what was synced
+fn sync_all_artefacts<P, I>(
+    out_dir: P,
+    artefacts: I,
+    options: &fetch_source::SourceOptionsTable,
+    force: bool,
+) -> Result<(), AppError>
+where
+    P: AsRef<std::path::Path>,
+    I: IntoIterator<Item = (String, std::path::PathBuf)>,
+{
+    let out_dir = out_dir.as_ref();
+    let mut state = sync::SyncState::load(out_dir)?;
+    let mut result = Ok(());
+    for (name, artefact_path) in artefacts {
+        if !artefact_path.is_dir() {
+            result = Err(AppError::missing_artefact_directory(name, artefact_path));
+            break;
+        }
+        let dest = destination(options, &name);
+        match state.sync(&artefact_path, out_dir, &dest, force) {
+            Ok(stats) => println!(
+                "synced '{name}' at '{}' ({} written, {} removed)",
+                out_dir.join(&dest).display(),
+                stats.written,
+                stats.removed
+            ),
+            Err(err) => {
+                result = Err(AppError::sync_failed(name, err));
+                break;
+            }
+        }
+    }
+    // Record the sources which were synced even if a later one failed
+    state.save()?;
+    result
+}
+
+/// Remove vendored sources which are no longer in the manifest, or whose definition has changed
+fn remove_stale_vendored(
+    vendor: &mut fetch_source::Vendor,
+    sources: &SourcesTable,
+) -> Result<(), AppError> {
+    let stale = vendor
+        .iter()
+        .filter(|(name, artefact)| sources.get(*name) != Some(artefact.source()))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    for name in stale {
+        if let Some(artefact) = vendor.remove(&name) {
+            let path = vendor.vendor_dir().join(artefact.path());
+            if path.exists() {
+                std::fs::remove_dir_all(&path)?;
+            }
+            println!("removed stale vendored source '{name}'");
+        }
+    }
+    Ok(())
+}
+
+/// Copy or link an artefact from its cache dir to `dest`
+fn copy_artefact<P>(
+    name: String,
+    artefact_path: P,
+    dest: std::path::PathBuf,
+    link_mode: link::LinkMode,
+) -> Result<(), AppError>
 where
     P: AsRef<std::path::Path>,
-    Q: AsRef<std::path::Path>,
 {
     if !artefact_path.as_ref().is_dir() {
         return Err(AppError::missing_artefact_directory(
@@ -130,13 +703,23 @@ where
             artefact_path.as_ref().to_path_buf(),
         ));
     }
-    let dest = out_dir.as_ref().join(Source::as_path_component(&name));
-    dircpy::copy_dir(artefact_path.as_ref(), &dest).map_err(|err| {
-        AppError::copy_artefact_failed(artefact_path.as_ref().to_path_buf(), dest, err)
+    let used = link::link_artefact(link_mode, artefact_path.as_ref(), &dest).map_err(|err| {
+        AppError::copy_artefact_failed(artefact_path.as_ref().to_path_buf(), dest.clone(), err)
     })?;
+    if used != link_mode {
+        println!("could not {link_mode} '{name}', copied some or all files instead");
+    }
     Ok(())
 }
 
+/// Report how much space was saved by deduplicating files
+fn report_dedup_stats(stats: fetch_source::DedupStats) {
+    println!(
+        "deduplicated {} of {} file(s), saving {} byte(s)",
+        stats.linked, stats.files, stats.bytes_saved
+    );
+}
+
 /// Report fetch results, including any errors and success messages.
 fn report_fetch_results(errors: Vec<fetch_source::FetchError>, num_sources: usize) {
     let num_errors = errors.len();
@@ -199,22 +782,73 @@ fn list(sources: fetch_source::SourcesTable, format: Option<OutputFormat>) -> Re
     Ok(())
 }
 
+/// Report the problems the lints find in the manifest's sources, failing if any are denied
+fn check(
+    manifest_file: &std::path::Path,
+    selection: &args::SourceSelection,
+    lints: &fetch_source::Lints,
+    vendor: Option<&fetch_source::Vendor>,
+    variables: &fetch_source::Variables,
+) -> Result<(), AppError> {
+    let sources = sources(manifest_file, selection, variables)?;
+    let findings = lints.check_with_vendor(&sources, vendor);
+    // Point each finding at the manifest which declares its source
+    let documents = selected_manifests(manifest_file, selection)
+        .into_iter()
+        .filter_map(|path| std::fs::read_to_string(&path).ok().map(|doc| (path, doc)))
+        .collect::<Vec<_>>();
+    let locate = |name: &str| {
+        documents
+            .iter()
+            .find_map(|(path, document)| fetch_source::Snippet::find(name, path, document))
+            .unwrap_or_else(|| fetch_source::Snippet::new(manifest_file))
+    };
+    let mut errors = 0;
+    for finding in &findings {
+        let style = match finding.severity() {
+            fetch_source::Severity::Deny => {
+                errors += 1;
+                console::Style::new().red().bold()
+            }
+            _ => console::Style::new().yellow().bold(),
+        };
+        eprintln!(
+            "{}: {}\n{}",
+            style.apply_to(format!("{}[{}]", finding.severity(), finding.lint())),
+            finding.message(),
+            locate(finding.source_name())
+        );
+    }
+    let warnings = findings.len() - errors;
+    println!(
+        "checked {} source(s): {warnings} warning(s), {errors} error(s)",
+        sources.len()
+    );
+    if errors > 0 {
+        Err(AppError::check(errors))
+    } else {
+        Ok(())
+    }
+}
+
 /// List artefacts in the given cache, formatted according to the specified output format.
 fn cached(
-    cache: &fetch_source::Cache,
+    cache: &mut fetch_source::Cache,
     format: Option<args::OutputFormat>,
     query_args: Option<args::CacheQuery>,
+    variables: &fetch_source::Variables,
 ) -> Result<(), AppError> {
     if let Some(query_args) = query_args {
         match query_args {
             args::CacheQuery::Manifest {
                 manifest_file,
+                selection,
                 source: source_name,
             } => {
-                let sources = sources(&manifest_file)?;
+                let sources = sources(&manifest_file, &selection, variables)?;
                 match sources.get(&source_name) {
                     Some(source) => {
-                        query_cache_for_source(cache.items(), source, format);
+                        query_cache_for_source(cache, source, format)?;
                     }
                     None => {
                         return Err(AppError::no_such_source(
@@ -225,7 +859,7 @@ fn cached(
                 }
             }
             args::CacheQuery::Source(source) => {
-                query_cache_for_source(cache.items(), &source, format);
+                query_cache_for_source(cache, &source, format)?;
             }
         }
     } else {
@@ -239,8 +873,14 @@ fn cached(
             None => {
                 let mut formatted = String::new();
                 for artefact in cache.items().values() {
+                    // Packed artefacts are unpacked to their path when next used
+                    let packed = if cache.is_packed(artefact.source()) {
+                        " (packed)"
+                    } else {
+                        ""
+                    };
                     formatted.push_str(&format!(
-                        "upstream: {}\npath:     {}\n\n",
+                        "upstream: {}\npath:     {}{packed}\n\n",
                         artefact.source(),
                         artefact.path().display()
                     ));
@@ -253,12 +893,22 @@ fn cached(
     Ok(())
 }
 
+/// Print the cached artefact for a source, if any. Packed artefacts are unpacked as the printed
+/// path is expected to exist, which is the only time the cache is saved.
 fn query_cache_for_source(
-    cache_items: &fetch_source::CacheItems,
+    cache: &mut fetch_source::Cache,
     source: &fetch_source::Source,
     format: Option<OutputFormat>,
-) {
-    if let Some(artefact) = cache_items.get(source) {
+) -> Result<(), AppError> {
+    if cache.is_packed(source) {
+        cache
+            .access(source)
+            .map_err(|err| AppError::unpack_failed(source.to_string(), err))?;
+        cache
+            .save()
+            .map_err(|err| AppError::cache_save_failed(cache.cache_file().to_path_buf(), err))?;
+    }
+    if let Some(artefact) = cache.get(source) {
         match format {
             Some(OutputFormat::Json) => {
                 println!(
@@ -273,8 +923,10 @@ fn query_cache_for_source(
                 );
             }
             None => {
-                println!("{}", artefact.path().display());
+                // Give the location within the cache the artefact was found in
+                println!("{}", cache.artefact_path(source).display());
             }
         }
     }
+    Ok(())
 }
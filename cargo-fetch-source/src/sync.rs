@@ -0,0 +1,191 @@
+//! Keep copies of artefacts in the output directory in sync with the cache.
+//!
+//! The contents of each synced directory are recorded in a state file in the output directory so
+//! that local modifications can be detected before they are overwritten.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The name of the file recording the synced directories within the output directory.
+pub const SYNC_FILE_NAME: &str = ".fetch-source-sync.json";
+
+/// An entry in a synced directory
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Entry {
+    Dir,
+    /// A regular file with the given SHA-256 digest
+    File(String),
+    /// A symlink with the given target
+    Symlink(PathBuf),
+}
+
+/// The entries of a directory, keyed by their path relative to it
+pub type Entries = BTreeMap<PathBuf, Entry>;
+
+/// How many entries of a directory were changed by syncing it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncStats {
+    pub written: usize,
+    pub removed: usize,
+}
+
+/// Errors which may occur while syncing a directory
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("{} has been modified since it was last synced ({}), use --force to overwrite it", dest.display(), format_paths(paths))]
+    LocallyModified { dest: PathBuf, paths: Vec<PathBuf> },
+    #[error("{} was not created by a previous sync, use --force to overwrite it", dest.display())]
+    Unmanaged { dest: PathBuf },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn format_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The synced directories within an output directory, keyed by their path relative to it
+#[derive(Debug, Default)]
+pub struct SyncState {
+    dirs: BTreeMap<PathBuf, Entries>,
+    state_file: PathBuf,
+}
+
+impl SyncState {
+    /// Read the sync state of `out_dir`, or start a new one if it has never been synced
+    pub fn load(out_dir: &Path) -> std::io::Result<Self> {
+        let state_file = out_dir.join(SYNC_FILE_NAME);
+        let dirs = if state_file.is_file() {
+            serde_json::from_str(&std::fs::read_to_string(&state_file)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { dirs, state_file })
+    }
+
+    /// Write the sync state back to the output directory
+    pub fn save(&self) -> std::io::Result<()> {
+        std::fs::write(&self.state_file, serde_json::to_string_pretty(&self.dirs)?)
+    }
+
+    /// Make `dest` (relative to the output directory) an exact copy of `from`, writing only the
+    /// entries which differ. Unless `force` is set, refuse to overwrite a destination which was
+    /// modified since it was last synced, or which wasn't created by syncing.
+    pub fn sync(
+        &mut self,
+        from: &Path,
+        out_dir: &Path,
+        dest: &Path,
+        force: bool,
+    ) -> Result<SyncStats, SyncError> {
+        let to = out_dir.join(dest);
+        // A symlink to the cache (see `--link-mode symlink`) holds no local changes
+        if to.is_symlink() {
+            std::fs::remove_file(&to)?;
+        }
+        let wanted = entries(from)?;
+        let current = if to.is_dir() {
+            entries(&to)?
+        } else {
+            Entries::new()
+        };
+
+        if to.exists() && !force {
+            match self.dirs.get(dest) {
+                Some(recorded) if *recorded != current => {
+                    return Err(SyncError::LocallyModified {
+                        dest: to,
+                        paths: changed_paths(recorded, &current),
+                    });
+                }
+                None if current != wanted => return Err(SyncError::Unmanaged { dest: to }),
+                _ => {}
+            }
+        }
+
+        let mut stats = SyncStats::default();
+        // Remove stale entries, children before their parents
+        for (path, entry) in current.iter().rev() {
+            if wanted.get(path) == Some(entry) {
+                continue;
+            }
+            let path = to.join(path);
+            match entry {
+                Entry::Dir if path.is_dir() => std::fs::remove_dir_all(&path)?,
+                Entry::Dir => continue,
+                Entry::File(_) | Entry::Symlink(_) => std::fs::remove_file(&path)?,
+            }
+            stats.removed += 1;
+        }
+        // Write new and changed entries, parents before their children
+        std::fs::create_dir_all(&to)?;
+        for (path, entry) in &wanted {
+            if current.get(path) == Some(entry) {
+                continue;
+            }
+            let (src, dst) = (from.join(path), to.join(path));
+            match entry {
+                Entry::Dir => std::fs::create_dir_all(&dst)?,
+                Entry::File(_) => {
+                    std::fs::copy(&src, &dst)?;
+                }
+                Entry::Symlink(target) => create_symlink(target, &src, &dst)?,
+            }
+            stats.written += 1;
+        }
+
+        self.dirs.insert(dest.to_path_buf(), wanted);
+        Ok(stats)
+    }
+}
+
+/// The paths which differ between two sets of entries
+fn changed_paths(a: &Entries, b: &Entries) -> Vec<PathBuf> {
+    let mut paths = a
+        .iter()
+        .filter(|(path, entry)| b.get(*path) != Some(*entry))
+        .chain(b.iter().filter(|(path, _)| !a.contains_key(*path)))
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// Recursively list the entries of `dir`. Symlinks are recorded rather than followed.
+fn entries(dir: &Path) -> std::io::Result<Entries> {
+    let mut entries = Entries::new();
+    collect_entries(dir, Path::new(""), &mut entries)?;
+    Ok(entries)
+}
+
+fn collect_entries(root: &Path, rel: &Path, entries: &mut Entries) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(root.join(rel))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = rel.join(entry.file_name());
+        if file_type.is_dir() {
+            entries.insert(path.clone(), Entry::Dir);
+            collect_entries(root, &path, entries)?;
+        } else if file_type.is_symlink() {
+            entries.insert(path, Entry::Symlink(std::fs::read_link(entry.path())?));
+        } else {
+            entries.insert(path, Entry::File(sha256::try_digest(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, _src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, dst)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::fs::copy(src, dst).map(|_| ())
+}
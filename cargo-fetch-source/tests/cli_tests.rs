@@ -14,6 +14,32 @@ fn test_list_command_with_missing_manifest() {
         .stderr(predicate::str::contains("Failed to read manifest file"));
 }
 
+#[test]
+fn test_list_command_with_invalid_manifest_shows_location() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         foo = { git = \"https://www.example.com/foo.git\", brnach = \"dev\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["list", "--manifest-file", manifest_path.to_str().unwrap()]);
+    cmd.assert()
+        .failure()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "Failed to parse manifest file: source 'foo' has unknown key 'brnach', did you mean 'branch'?",
+        ))
+        .stderr(predicate::str::contains(format!(
+            "--> {}:2:50",
+            manifest_path.display()
+        )))
+        .stderr(predicate::str::contains("^^^^^^"));
+}
+
 #[test]
 fn test_help_command_succeeds() {
     let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
@@ -229,7 +255,7 @@ fn test_cached_command_query_manifest_source() {
     let cache_path = temp_dir.path().join("cache");
     let cargo_toml = r#"
 [package.metadata.fetch-source]
-"syn" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.109.tar.gz" }
+"syn::1.0.109" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.109.tar.gz" }
 "syn::1.0.0" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.0.tar.gz" }
     "#;
     std::fs::write(&manifest_path, cargo_toml).unwrap();
@@ -249,7 +275,7 @@ fn test_cached_command_query_manifest_source() {
     // The digest of the other source which was cached, but that shouldn't appear in the output
     let syn_100_digest = "6366d155d905264e8697cbe862fe2d8519c1d958af0e4d784b79ca89a540678b";
 
-    // Query the cache for the "syn" source
+    // Query the cache for the "syn::1.0.109" source
     let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
     cmd.args([
         "cached",
@@ -258,7 +284,7 @@ fn test_cached_command_query_manifest_source() {
         "--manifest-file",
         manifest_path.to_str().unwrap(),
         "--source",
-        "syn",
+        "syn::1.0.109",
     ]);
     let result = cmd.assert().success();
     let output = String::from_utf8_lossy(&result.get_output().stdout);
@@ -279,7 +305,7 @@ fn test_cached_command_query_source_arg_tar() {
     let cache_path = temp_dir.path().join("cache");
     let cargo_toml = r#"
 [package.metadata.fetch-source]
-"syn" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.109.tar.gz" }
+"syn::1.0.109" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.109.tar.gz" }
 "syn::1.0.0" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.0.tar.gz" }
     "#;
     std::fs::write(&manifest_path, cargo_toml).unwrap();
@@ -327,7 +353,7 @@ fn test_cached_command_query_source_arg_git() {
     let cache_path = temp_dir.path().join("cache");
     let cargo_toml = r#"
 [package.metadata.fetch-source]
-"syn" = { git = "https://github.com/dtolnay/syn.git" }
+"syn::git" = { git = "https://github.com/dtolnay/syn.git" }
 "syn::1.0.0" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.0.tar.gz" }
     "#;
     std::fs::write(&manifest_path, cargo_toml).unwrap();
@@ -375,7 +401,7 @@ fn test_cached_command_query_source_arg_git_tag() {
     let cache_path = temp_dir.path().join("cache");
     let cargo_toml = r#"
 [package.metadata.fetch-source]
-"syn" = { git = "https://github.com/dtolnay/syn.git", tag = "1.0.0" }
+"syn::git" = { git = "https://github.com/dtolnay/syn.git", tag = "1.0.0" }
 "syn::1.0.0" = { tar = "https://github.com/dtolnay/syn/archive/refs/tags/1.0.0.tar.gz" }
     "#;
     std::fs::write(&manifest_path, cargo_toml).unwrap();
@@ -465,3 +491,1375 @@ fn test_environment_variable_detection_out_dir() {
     // Should succeed as OUT_DIR is provided via environment
     cmd.assert().success();
 }
+
+/// Write a cache file in `cache_dir` containing the given sources, with an artefact directory for
+/// each containing a single file.
+fn write_cache_with_sources(cache_dir: &std::path::Path, sources: &[Source]) {
+    std::fs::create_dir_all(cache_dir).unwrap();
+    let mut items = serde_json::Map::new();
+    for source in sources {
+        let digest = Source::digest(source);
+        let artefact_dir = cache_dir.join(digest.as_ref());
+        std::fs::create_dir_all(&artefact_dir).unwrap();
+        std::fs::write(artefact_dir.join("README"), "cached").unwrap();
+        items.insert(
+            digest.to_string(),
+            serde_json::json!({ "source": source, "path": artefact_dir }),
+        );
+    }
+    std::fs::write(
+        cache_dir.join("fetch-source-cache.json"),
+        serde_json::to_string_pretty(&items).unwrap(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_cached_command_query_read_only_cache() {
+    let temp_dir = tempdir().unwrap();
+    let shared_cache = temp_dir.path().join("shared");
+    let user_cache = temp_dir.path().join("user");
+    let source = Source::git("https://example.com/repo.git", None, false);
+    write_cache_with_sources(&shared_cache, std::slice::from_ref(&source));
+    write_cache_with_sources(&user_cache, &[]);
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "--cache",
+        user_cache.to_str().unwrap(),
+        "--read-only-cache",
+        shared_cache.to_str().unwrap(),
+        "--git",
+        "https://example.com/repo.git",
+    ]);
+    let result = cmd.assert().success();
+    let output = String::from_utf8_lossy(&result.get_output().stdout);
+    assert!(output.contains(Source::digest(&source).as_ref()));
+    assert!(output.contains("shared"));
+}
+
+#[test]
+fn test_fetch_command_uses_read_only_cache_from_env() {
+    // The source can't be fetched, so the fetch only succeeds if the read-only cache is used
+    let temp_dir = tempdir().unwrap();
+    let shared_cache = temp_dir.path().join("shared");
+    let user_cache = temp_dir.path().join("user");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let source = Source::git("https://www.example.com/does-not-exist.git", None, false);
+    write_cache_with_sources(&shared_cache, &[source]);
+
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         shared = { git = \"https://www.example.com/does-not-exist.git\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.env("CARGO_FETCH_SOURCE_READ_ONLY_CACHE", &shared_cache);
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        user_cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    assert!(out_dir.join("shared/README").is_file());
+
+    // Nothing was added to the writable cache
+    let user_items: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(user_cache.join("fetch-source-cache.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(user_items, serde_json::json!({}));
+}
+
+#[test]
+fn test_cached_export_import_roundtrip() {
+    let temp_dir = tempdir().unwrap();
+    let src_cache = temp_dir.path().join("src-cache");
+    let dst_cache = temp_dir.path().join("dst-cache");
+    let bundle = temp_dir.path().join("sources.tar.gz");
+    let wanted = Source::git("https://example.com/wanted.git", None, false);
+    let unwanted = Source::git("https://example.com/unwanted.git", None, false);
+    write_cache_with_sources(&src_cache, &[wanted.clone(), unwanted.clone()]);
+
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         wanted = { git = \"https://example.com/wanted.git\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "export",
+        bundle.to_str().unwrap(),
+        "--cache",
+        src_cache.to_str().unwrap(),
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("exported 1 source(s)"));
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "import",
+        bundle.to_str().unwrap(),
+        "--cache",
+        dst_cache.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("imported 1 source(s)"));
+
+    let imported = dst_cache.join(Source::digest(&wanted).as_ref());
+    assert!(imported.join("README").is_file());
+    assert!(!dst_cache.join(Source::digest(&unwanted).as_ref()).exists());
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "--cache",
+        dst_cache.to_str().unwrap(),
+        "--git",
+        "https://example.com/wanted.git",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(Source::digest(&wanted).as_ref()));
+}
+
+#[test]
+fn test_cached_export_uncached_source_leaves_no_bundle() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let bundle = temp_dir.path().join("bundle.tar.gz");
+    write_cache_with_sources(&cache, &[]);
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]
+         uncached = { git = \"https://example.com/uncached.git\" }
+",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "export",
+        bundle.to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("source is not cached"));
+    assert!(!bundle.exists());
+    assert!(!temp_dir.path().join("bundle.tar.gz.partial").exists());
+}
+
+#[test]
+fn test_cached_export_unknown_source_fails() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    write_cache_with_sources(&cache, &[]);
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(&manifest_path, "[package.metadata.fetch-source]\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "export",
+        temp_dir.path().join("bundle.tar.gz").to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--source",
+        "missing",
+    ]);
+    cmd.assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("no source called 'missing'"));
+}
+
+#[test]
+fn test_vendor_then_fetch_uses_vendored_copy() {
+    // The source can't be fetched, so it is vendored from a read-only cache. Afterwards, fetching
+    // must succeed without any cache by using the vendored copy.
+    let temp_dir = tempdir().unwrap();
+    let shared_cache = temp_dir.path().join("shared");
+    let source = Source::git("https://www.example.com/does-not-exist.git", None, false);
+    write_cache_with_sources(&shared_cache, &[source]);
+
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         \"vendored::source\" = { git = \"https://www.example.com/does-not-exist.git\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "vendor",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        temp_dir.path().join("user1").to_str().unwrap(),
+        "--read-only-cache",
+        shared_cache.to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    let vendor_dir = temp_dir.path().join("fetch-source-vendor");
+    assert!(vendor_dir.join("vendored/source/README").is_file());
+    assert!(vendor_dir.join("fetch-source-vendor.json").is_file());
+
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        temp_dir.path().join("user2").to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("vendored 'vendored::source'"));
+    assert!(out_dir.join("vendored/source/README").is_file());
+
+    // Vendoring again once the source is removed from the manifest removes the vendored copy
+    std::fs::write(&manifest_path, "[package.metadata.fetch-source]\n").unwrap();
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "vendor",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        temp_dir.path().join("user1").to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    assert!(!vendor_dir.join("vendored/source").exists());
+}
+
+#[test]
+fn test_cached_merge_copies_missing_sources() {
+    let temp_dir = tempdir().unwrap();
+    let shard = temp_dir.path().join("shard");
+    let combined = temp_dir.path().join("combined");
+    let first = Source::git("https://example.com/first.git", None, false);
+    let second = Source::git("https://example.com/second.git", None, false);
+    write_cache_with_sources(&shard, &[first.clone(), second.clone()]);
+    write_cache_with_sources(&combined, std::slice::from_ref(&first));
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "merge",
+        shard.to_str().unwrap(),
+        "--cache",
+        combined.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("merged 1 source(s)"));
+    assert!(
+        combined
+            .join(Source::digest(&second).as_ref())
+            .join("README")
+            .is_file()
+    );
+
+    let items: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(combined.join("fetch-source-cache.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(items.as_object().unwrap().len(), 2);
+}
+
+#[test]
+fn test_cached_merge_with_itself_fails() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    write_cache_with_sources(&cache, &[]);
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "merge",
+        cache.to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("cannot merge a cache with itself"));
+}
+
+#[test]
+fn test_cached_dedup_links_identical_files() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let first = Source::git("https://example.com/first.git", None, false);
+    let second = Source::git("https://example.com/second.git", None, false);
+    write_cache_with_sources(&cache, &[first, second]);
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["cached", "dedup", "--cache", cache.to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("deduplicated 1 of 2 file(s)"));
+}
+
+#[test]
+fn test_cached_pack_then_query_unpacks() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let source = Source::git("https://example.com/repo.git", None, false);
+    write_cache_with_sources(&cache, std::slice::from_ref(&source));
+    let artefact_dir = cache.join(Source::digest(&source).as_ref());
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "cached",
+        "pack",
+        "--unused-for",
+        "0",
+        "--cache",
+        cache.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("packed 1 unused source(s)"));
+    assert!(!artefact_dir.exists());
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["cached", "--cache", cache.to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(" (packed)"));
+
+    let query = || {
+        let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+        cmd.args([
+            "cached",
+            "--cache",
+            cache.to_str().unwrap(),
+            "--git",
+            "https://example.com/repo.git",
+        ]);
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(Source::digest(&source).as_ref()));
+    };
+    query();
+    assert!(artefact_dir.join("README").is_file());
+
+    // Querying an unpacked artefact doesn't write the cache file
+    let cache_file = cache.join("fetch-source-cache.json");
+    let saved = std::fs::read_to_string(&cache_file).unwrap();
+    query();
+    assert_eq!(std::fs::read_to_string(&cache_file).unwrap(), saved);
+}
+
+/// Fetch a single cached source into a fresh output directory using `link_mode`, returning the
+/// cached and output paths of its README
+fn fetch_cached_source_with_link_mode(
+    temp_dir: &std::path::Path,
+    link_mode: &str,
+) -> (std::path::PathBuf, std::path::PathBuf) {
+    let cache = temp_dir.join("cache");
+    let out_dir = temp_dir.join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let source = Source::git("https://www.example.com/does-not-exist.git", None, false);
+    write_cache_with_sources(&cache, std::slice::from_ref(&source));
+
+    let manifest_path = temp_dir.join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         linked = { git = \"https://www.example.com/does-not-exist.git\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+        "--link-mode",
+        link_mode,
+    ]);
+    cmd.assert().success();
+    (
+        cache.join(Source::digest(&source).as_ref()).join("README"),
+        out_dir.join("linked/README"),
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fetch_command_link_mode_hardlink() {
+    use std::os::unix::fs::MetadataExt;
+    let temp_dir = tempdir().unwrap();
+    let (cached, linked) = fetch_cached_source_with_link_mode(temp_dir.path(), "hardlink");
+    let cached = std::fs::metadata(cached).unwrap();
+    let linked = std::fs::metadata(linked).unwrap();
+    assert_eq!(cached.ino(), linked.ino());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fetch_command_link_mode_symlink() {
+    let temp_dir = tempdir().unwrap();
+    let (cached, linked) = fetch_cached_source_with_link_mode(temp_dir.path(), "symlink");
+    assert!(linked.parent().unwrap().is_symlink());
+    assert_eq!(
+        linked.canonicalize().unwrap(),
+        cached.canonicalize().unwrap()
+    );
+}
+
+#[test]
+fn test_fetch_command_link_mode_reflink_falls_back_to_copy() {
+    // Whether or not the filesystem supports reflinks, the output has the cached contents
+    let temp_dir = tempdir().unwrap();
+    let (_, linked) = fetch_cached_source_with_link_mode(temp_dir.path(), "reflink");
+    assert_eq!(std::fs::read_to_string(linked).unwrap(), "cached");
+}
+
+#[test]
+fn test_fetch_command_sync_removes_stale_files_and_protects_local_changes() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let source = Source::git("https://www.example.com/does-not-exist.git", None, false);
+    write_cache_with_sources(&cache, std::slice::from_ref(&source));
+    let artefact_dir = cache.join(Source::digest(&source).as_ref());
+    std::fs::write(artefact_dir.join("old.txt"), "old").unwrap();
+
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         synced = { git = \"https://www.example.com/does-not-exist.git\" }\n",
+    )
+    .unwrap();
+    let sync = |force: bool| {
+        let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+        cmd.args([
+            "fetch",
+            "--manifest-file",
+            manifest_path.to_str().unwrap(),
+            "--cache",
+            cache.to_str().unwrap(),
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+            "--sync",
+        ]);
+        if force {
+            cmd.arg("--force");
+        }
+        cmd.assert()
+    };
+
+    sync(false).success();
+    assert!(out_dir.join("synced/old.txt").is_file());
+
+    // The cached artefact changes: stale files are removed and only changed files are written
+    std::fs::remove_file(artefact_dir.join("old.txt")).unwrap();
+    std::fs::write(artefact_dir.join("new.txt"), "new").unwrap();
+    sync(false)
+        .success()
+        .stdout(predicate::str::contains("(1 written, 1 removed)"));
+    assert!(!out_dir.join("synced/old.txt").exists());
+    assert!(out_dir.join("synced/new.txt").is_file());
+
+    // Local changes are not overwritten unless forced
+    std::fs::write(out_dir.join("synced/README"), "edited").unwrap();
+    sync(false)
+        .failure()
+        .stderr(predicate::str::contains("has been modified"));
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("synced/README")).unwrap(),
+        "edited"
+    );
+    sync(true).success();
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("synced/README")).unwrap(),
+        "cached"
+    );
+}
+
+#[test]
+fn test_fetch_command_copies_to_dest() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let source = Source::git("https://www.example.com/does-not-exist.git", None, false);
+    write_cache_with_sources(&cache, &[source]);
+
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         \"vendor::zlib::1.3\" = { git = \"https://www.example.com/does-not-exist.git\", dest = \"third_party/zlib\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    assert!(out_dir.join("third_party/zlib/README").is_file());
+    assert!(!out_dir.join("vendor").exists());
+}
+
+#[test]
+fn test_fetch_command_skips_sources_for_disabled_features() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let source = Source::git("https://www.example.com/always.git", None, false);
+    write_cache_with_sources(&cache, &[source]);
+
+    // The feature-gated source isn't cached, so fetching it would fail
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         always = { git = \"https://www.example.com/always.git\" }\n\
+         bundled = { git = \"https://www.example.com/bundled.git\", features = [\"bundled\"] }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+        "--features",
+        "other,static",
+    ]);
+    cmd.assert().success();
+    assert!(out_dir.join("always/README").is_file());
+    assert!(!out_dir.join("bundled").exists());
+}
+
+#[test]
+fn test_fetch_command_skips_sources_for_other_targets() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    let source = Source::git("https://www.example.com/linux.git", None, false);
+    write_cache_with_sources(&cache, &[source]);
+
+    // The Windows source isn't cached, so fetching it would fail
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         linux = { git = \"https://www.example.com/linux.git\", target = 'cfg(target_os = \"linux\")' }\n\
+         windows = { git = \"https://www.example.com/windows.git\", target = [\"x86_64-pc-windows-msvc\"] }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        manifest_path.to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+        "--target",
+        "x86_64-unknown-linux-gnu",
+    ]);
+    cmd.assert().success();
+    assert!(out_dir.join("linux/README").is_file());
+    assert!(!out_dir.join("windows").exists());
+}
+
+#[test]
+fn test_fetch_command_workspace_fetches_union_of_members() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    write_cache_with_sources(
+        &cache,
+        &[
+            Source::git("https://www.example.com/shared.git", None, false),
+            Source::git("https://www.example.com/a.git", None, false),
+            Source::git("https://www.example.com/b.git", None, false),
+        ],
+    );
+
+    let root = temp_dir.path().join("workspace");
+    std::fs::create_dir_all(root.join("crates/a")).unwrap();
+    std::fs::create_dir_all(root.join("crates/b")).unwrap();
+    std::fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\n\
+         members = [\"crates/*\"]\n\
+         [workspace.metadata.fetch-source]\n\
+         shared = { git = \"https://www.example.com/shared.git\" }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("crates/a/Cargo.toml"),
+        "[package.metadata.fetch-source]\n\
+         shared = { git = \"https://www.example.com/shared.git\" }\n\
+         a = { git = \"https://www.example.com/a.git\" }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("crates/b/Cargo.toml"),
+        "[package.metadata.fetch-source]\n\
+         b = { git = \"https://www.example.com/b.git\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--workspace",
+        "--manifest-file",
+        root.join("crates/a/Cargo.toml").to_str().unwrap(),
+        "--cache",
+        cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    for name in ["shared", "a", "b"] {
+        assert!(out_dir.join(name).join("README").is_file());
+    }
+}
+
+#[test]
+fn test_list_command_workspace_with_relative_manifest() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("crates/a")).unwrap();
+    std::fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\n\
+         members = [\"crates/*\"]\n\
+         [workspace.metadata.fetch-source]\n\
+         shared = { git = \"https://www.example.com/shared.git\" }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("crates/a/Cargo.toml"),
+        "[package.metadata.fetch-source]\n\
+         a = { git = \"https://www.example.com/a.git\" }\n",
+    )
+    .unwrap();
+
+    // From the workspace root, and from a member
+    for dir in [root.to_path_buf(), root.join("crates/a")] {
+        let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+        cmd.current_dir(&dir).args([
+            "list",
+            "--workspace",
+            "--format",
+            "json",
+            "-m",
+            "Cargo.toml",
+        ]);
+        let output = cmd.assert().success();
+        let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+        let sources_table: SourcesTable = serde_json::from_str(&stdout).unwrap();
+        let mut names = sources_table.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "shared"], "from {}", dir.display());
+    }
+}
+
+#[test]
+fn test_fetch_command_workspace_conflicting_sources_fails() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("a")).unwrap();
+    std::fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\n\
+         members = [\"a\"]\n\
+         [workspace.metadata.fetch-source]\n\
+         foo = { git = \"https://www.example.com/foo.git\" }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("a/Cargo.toml"),
+        "[package.metadata.fetch-source]\n\
+         foo = { git = \"https://www.example.com/foo.git\", branch = \"dev\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--workspace",
+        "--manifest-file",
+        root.join("Cargo.toml").to_str().unwrap(),
+        "--cache",
+        root.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to parse manifest file"));
+}
+
+#[test]
+fn test_fetch_command_cargo_metadata_selects_package() {
+    let temp_dir = tempdir().unwrap();
+    let cache = temp_dir.path().join("cache");
+    let out_dir = temp_dir.path().join("output");
+    std::fs::create_dir(&out_dir).unwrap();
+    write_cache_with_sources(
+        &cache,
+        &[
+            Source::git("https://www.example.com/a.git", None, false),
+            Source::git("https://www.example.com/b.git", None, false),
+        ],
+    );
+
+    let root = temp_dir.path().join("workspace");
+    std::fs::create_dir_all(root.join("a/src")).unwrap();
+    std::fs::create_dir_all(root.join("b/src")).unwrap();
+    std::fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"a\", \"b\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+    for name in ["a", "b"] {
+        std::fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+        std::fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\n\
+                 name = \"{name}\"\n\
+                 version = \"0.1.0\"\n\
+                 edition = \"2021\"\n\
+                 [package.metadata.fetch-source]\n\
+                 {name} = {{ git = \"https://www.example.com/{name}.git\" }}\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        root.join("Cargo.toml").to_str().unwrap(),
+        "-p",
+        "b",
+        "--cache",
+        cache.to_str().unwrap(),
+        "--out-dir",
+        out_dir.to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    assert!(!out_dir.join("a").exists());
+    assert!(out_dir.join("b/README").is_file());
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "fetch",
+        "--manifest-file",
+        root.join("Cargo.toml").to_str().unwrap(),
+        "-p",
+        "c",
+        "--cache",
+        cache.to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("no package called 'c'"));
+}
+
+#[test]
+fn test_check_command_reports_warnings() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        r#"
+[package.metadata.fetch-source]
+foo = { git = "git://www.example.com/foo.git", branch = "dev" }
+bar = { tar = "https://www.example.com/bar.tar.gz", sha256 = "abc" }
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["check", "--manifest-file", manifest_path.to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "checked 2 source(s): 2 warning(s), 0 error(s)",
+        ))
+        .stderr(predicate::str::contains(
+            "warning[insecure-url]: source 'foo' is fetched over an insecure URL",
+        ))
+        .stderr(predicate::str::contains(
+            "warning[floating-reference]: source 'foo' follows branch 'dev'",
+        ));
+}
+
+#[test]
+fn test_check_command_vendored_sources_are_pinned() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         foo = { git = \"https://www.example.com/foo.git\", branch = \"dev\" }\n",
+    )
+    .unwrap();
+    let sources =
+        fetch_source::try_parse_toml(std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+    let vendor_dir = temp_dir.path().join(fetch_source::DEFAULT_VENDOR_DIR);
+    std::fs::create_dir(&vendor_dir).unwrap();
+    let mut vendor = fetch_source::Vendor::load_or_create(&vendor_dir).unwrap();
+    vendor.insert("foo".into(), sources["foo"].clone());
+    vendor.save().unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["check", "-D", "warnings", "--manifest-file"])
+        .arg(&manifest_path);
+    cmd.assert().success().stdout(predicate::str::contains(
+        "checked 1 source(s): 0 warning(s), 0 error(s)",
+    ));
+}
+
+#[test]
+fn test_check_command_warn_warnings_resets_every_lint() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        r#"
+[package.metadata.fetch-source]
+foo = { tar = "https://www.example.com/foo.tar.gz" }
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["check", "--manifest-file", manifest_path.to_str().unwrap()]);
+    cmd.args(["-A", "warnings", "-W", "warnings"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "checked 1 source(s): 1 warning(s), 0 error(s)",
+        ))
+        .stderr(predicate::str::contains("warning[missing-checksum]"));
+}
+
+#[test]
+fn test_check_command_fails_for_denied_lints() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        r#"
+[package.metadata.fetch-source]
+foo = { tar = "https://www.example.com/foo.tar.gz" }
+bar = { git = "https://www.example.com/bar.git" }
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["check", "--manifest-file", manifest_path.to_str().unwrap()]);
+    cmd.args(["-D", "warnings", "-A", "floating-reference"]);
+    cmd.assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "error[missing-checksum]: source 'foo' is an archive without a 'sha256' checksum",
+        ))
+        .stderr(predicate::str::contains(format!(
+            " --> {}:3:1\n  |\n3 | foo = ",
+            manifest_path.display()
+        )))
+        .stderr(predicate::str::contains("floating-reference").not())
+        .stderr(predicate::str::contains("check failed: 1 error(s) found"));
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["check", "--manifest-file", manifest_path.to_str().unwrap()]);
+    cmd.args(["--deny", "no-such-lint"]);
+    cmd.assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("unknown lint 'no-such-lint'"));
+}
+
+#[test]
+fn test_schema_command_prints_json_schema() {
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    let output = cmd.arg("schema").assert().success().get_output().clone();
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        schema["properties"]["package"]["properties"]["metadata"]["properties"]["fetch-source"]["$ref"],
+        "#/$defs/SourcesTable"
+    );
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    let output = cmd
+        .args(["schema", "--table"])
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(schema["additionalProperties"]["$ref"], "#/$defs/Entry");
+    assert!(schema["$defs"]["GitEntry"]["properties"]["git"].is_object());
+}
+
+#[test]
+fn test_add_command_creates_table_and_preserves_formatting() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "# A comment\n[package]\nname = \"foo\"   # aligned\n\n[dependencies]\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["add", "syn::latest", "--manifest-file"])
+        .arg(&manifest_path)
+        .args(["--git", "https://www.example.com/syn.git", "--tag", "v1"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("added 'syn::latest'"));
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["add", "data", "--manifest-file"])
+        .arg(&manifest_path)
+        .args(["--tar", "https://www.example.com/data.tar.gz"])
+        .args(["--sha256", "abc123", "--dest", "vendor/data"]);
+    cmd.assert().success();
+
+    assert_eq!(
+        std::fs::read_to_string(&manifest_path).unwrap(),
+        r#"# A comment
+[package]
+name = "foo"   # aligned
+
+[package.metadata.fetch-source]
+"syn::latest" = { git = "https://www.example.com/syn.git", tag = "v1" }
+data = { tar = "https://www.example.com/data.tar.gz", sha256 = "abc123", dest = "vendor/data" }
+
+[dependencies]
+"#
+    );
+}
+
+#[test]
+fn test_add_command_rejects_existing_and_invalid_sources() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    let manifest = r#"[package]
+name = "foo"
+
+[package.metadata.fetch-source]
+foo = { git = "https://www.example.com/foo.git" }
+"#;
+    std::fs::write(&manifest_path, manifest).unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["add", "foo", "--manifest-file"])
+        .arg(&manifest_path)
+        .args(["--git", "https://www.example.com/other.git"]);
+    cmd.assert()
+        .failure()
+        .code(3)
+        .stderr(predicate::str::contains("source 'foo' already exists"));
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["add", "bar", "--manifest-file"])
+        .arg(&manifest_path)
+        .args([
+            "--git",
+            "https://www.example.com/bar.git",
+            "--dest",
+            "../bar",
+        ]);
+    cmd.assert()
+        .failure()
+        .code(3)
+        .stderr(predicate::str::contains("Failed to parse manifest file"));
+
+    // The manifest is left untouched
+    assert_eq!(std::fs::read_to_string(&manifest_path).unwrap(), manifest);
+}
+
+#[test]
+fn test_remove_command_preserves_formatting() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        r#"[package]
+name = "foo"   # aligned
+
+[package.metadata.fetch-source]
+# Kept
+foo = { git = "https://www.example.com/foo.git" }
+bar = { git = "https://www.example.com/bar.git" }
+"baz::qux" = "https://www.example.com/baz.tar.gz"
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["remove", "bar", "baz::qux", "--manifest-file"])
+        .arg(&manifest_path);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("removed 'bar'"))
+        .stdout(predicate::str::contains("removed 'baz::qux'"));
+
+    assert_eq!(
+        std::fs::read_to_string(&manifest_path).unwrap(),
+        r#"[package]
+name = "foo"   # aligned
+
+[package.metadata.fetch-source]
+# Kept
+foo = { git = "https://www.example.com/foo.git" }
+"#
+    );
+}
+
+#[test]
+fn test_remove_command_rejects_unknown_source() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    let manifest = r#"[package]
+name = "foo"
+
+[package.metadata.fetch-source]
+foo = { git = "https://www.example.com/foo.git" }
+"#;
+    std::fs::write(&manifest_path, manifest).unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["remove", "foo", "bar", "--manifest-file"])
+        .arg(&manifest_path);
+    cmd.assert()
+        .failure()
+        .code(3)
+        .stderr(predicate::str::contains("no source called 'bar'"));
+
+    // The manifest is left untouched
+    assert_eq!(std::fs::read_to_string(&manifest_path).unwrap(), manifest);
+}
+
+#[test]
+fn test_remove_command_evicts_unreferenced_sources() {
+    let temp_dir = tempdir().unwrap();
+    let cache_dir = temp_dir.path().join("cache");
+    let unused = Source::git("https://www.example.com/unused.git", None, false);
+    let shared = Source::git("https://www.example.com/shared.git", None, false);
+    let elsewhere = Source::git("https://www.example.com/elsewhere.git", None, false);
+    write_cache_with_sources(
+        &cache_dir,
+        &[unused.clone(), shared.clone(), elsewhere.clone()],
+    );
+
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        r#"[package]
+name = "foo"
+
+[package.metadata.fetch-source]
+unused = { git = "https://www.example.com/unused.git" }
+shared = { git = "https://www.example.com/shared.git" }
+alias = { git = "https://www.example.com/shared.git" }
+elsewhere = { git = "https://www.example.com/elsewhere.git" }
+"#,
+    )
+    .unwrap();
+    let other_path = temp_dir.path().join("other.toml");
+    std::fs::write(
+        &other_path,
+        r#"[package.metadata.fetch-source]
+mine = { git = "https://www.example.com/elsewhere.git" }
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["remove", "unused", "shared", "elsewhere", "--evict"])
+        .arg("--manifest-file")
+        .arg(&manifest_path)
+        .arg("--cache")
+        .arg(&cache_dir)
+        .arg("--other-manifest")
+        .arg(&other_path);
+    let result = cmd.assert().success();
+    let output = String::from_utf8_lossy(&result.get_output().stdout);
+    assert!(output.contains("evicted 'unused' from the cache"));
+    assert!(!output.contains("evicted 'shared'"));
+    assert!(!output.contains("evicted 'elsewhere'"));
+
+    let digest = |source: &Source| Source::digest(source).to_string();
+    assert!(!cache_dir.join(digest(&unused)).exists());
+    assert!(cache_dir.join(digest(&shared)).is_dir());
+    assert!(cache_dir.join(digest(&elsewhere)).is_dir());
+    let items: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(cache_dir.join("fetch-source-cache.json")).unwrap(),
+    )
+    .unwrap();
+    let items = items.as_object().unwrap();
+    assert!(!items.contains_key(&digest(&unused)));
+    assert!(items.contains_key(&digest(&shared)));
+    assert!(items.contains_key(&digest(&elsewhere)));
+}
+
+#[test]
+fn test_remove_command_evict_keeps_workspace_sources_with_relative_manifest() {
+    let temp_dir = tempdir().unwrap();
+    let cache_dir = temp_dir.path().join("cache");
+    let shared = Source::git("https://www.example.com/shared.git", None, false);
+    write_cache_with_sources(&cache_dir, std::slice::from_ref(&shared));
+
+    let root = temp_dir.path().join("workspace");
+    std::fs::create_dir_all(root.join("a")).unwrap();
+    std::fs::create_dir_all(root.join("b")).unwrap();
+    let workspace = "[workspace]\nmembers = [\"a\", \"b\"]\n";
+    std::fs::write(root.join("Cargo.toml"), workspace).unwrap();
+    for member in ["a", "b"] {
+        std::fs::write(
+            root.join(member).join("Cargo.toml"),
+            "[package.metadata.fetch-source]\n\
+             shared = { git = \"https://www.example.com/shared.git\" }\n",
+        )
+        .unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.current_dir(root.join("a"))
+        .args(["remove", "shared", "--evict", "-m", "Cargo.toml", "--cache"])
+        .arg(&cache_dir);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("evicted").not());
+    assert!(cache_dir.join(Source::digest(&shared).as_ref()).is_dir());
+
+    // A workspace which can't be read is reported rather than ignored
+    std::fs::write(root.join("Cargo.toml"), "[workspace\n").unwrap();
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.current_dir(root.join("b"))
+        .args(["remove", "shared", "--evict", "-m", "Cargo.toml", "--cache"])
+        .arg(&cache_dir);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Failed to find the workspace containing",
+    ));
+    assert!(cache_dir.join(Source::digest(&shared).as_ref()).is_dir());
+}
+
+#[test]
+fn test_remove_command_other_manifest_requires_evict() {
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["remove", "foo", "--other-manifest", "Cargo.toml"]);
+    cmd.assert().failure().code(2);
+}
+
+#[test]
+fn test_add_command_in_virtual_manifest() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(&manifest_path, "[workspace]\nmembers = []\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["add", "foo", "--manifest-file"])
+        .arg(&manifest_path)
+        .args(["--git", "https://www.example.com/foo.git"]);
+    cmd.assert().success();
+
+    assert_eq!(
+        std::fs::read_to_string(&manifest_path).unwrap(),
+        r#"[workspace]
+members = []
+
+[workspace.metadata.fetch-source]
+foo = { git = "https://www.example.com/foo.git" }
+"#
+    );
+
+    // The name is rejected before the archive is downloaded, which would fail here
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["add", "foo", "--manifest-file"])
+        .arg(&manifest_path)
+        .args(["--tar", "https://www.example.com/does-not-exist.tar.gz"])
+        .arg("--sha256");
+    cmd.assert()
+        .failure()
+        .code(3)
+        .stderr(predicate::str::contains("source 'foo' already exists"));
+}
+
+#[test]
+fn test_list_command_expands_target_to_host() {
+    let temp_dir = tempdir().unwrap();
+    let manifest_path = temp_dir.path().join("Cargo.toml");
+    std::fs::write(
+        &manifest_path,
+        "[package.metadata.fetch-source]\n\
+         foo = { git = \"https://www.example.com/foo-${target}.git\" }\n",
+    )
+    .unwrap();
+
+    // `TARGET` is only read by build scripts
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.env("TARGET", "not-the-host")
+        .args(["list", "--format", "json", "--manifest-file"])
+        .arg(&manifest_path);
+    let output = cmd.assert().success();
+    let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    let sources_table: SourcesTable = serde_json::from_str(&stdout).unwrap();
+    let Some(Source::Git(git)) = sources_table.get("foo") else {
+        panic!("Expected foo to be a Git source");
+    };
+    assert!(git.upstream().starts_with("https://www.example.com/foo-"));
+    assert!(!git.upstream().contains("${target}"));
+    assert!(!git.upstream().contains("not-the-host"));
+}
+
+#[test]
+fn test_list_command_selects_package_with_manifest_path() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path();
+    std::fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"a\", \"b\"]\nresolver = \"2\"\n",
+    )
+    .unwrap();
+    for name in ["a", "b"] {
+        std::fs::create_dir_all(root.join(name).join("src")).unwrap();
+        std::fs::write(root.join(name).join("src/lib.rs"), "").unwrap();
+        std::fs::write(
+            root.join(name).join("Cargo.toml"),
+            format!(
+                "[package]\n\
+                 name = \"{name}\"\n\
+                 version = \"0.1.0\"\n\
+                 edition = \"2021\"\n\
+                 [package.metadata.fetch-source]\n\
+                 {name} = {{ git = \"https://www.example.com/{name}.git\" }}\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["list", "--format", "json", "-p", "b", "--manifest-path"])
+        .arg(root.join("Cargo.toml"));
+    let output = cmd.assert().success();
+    let stdout = String::from_utf8(output.get_output().stdout.clone()).unwrap();
+    let sources_table: SourcesTable = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(sources_table.len(), 1);
+    assert!(sources_table.contains_key("b"));
+}
+
+#[test]
+fn test_check_command_workspace_checks_every_member() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("a")).unwrap();
+    std::fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\n\
+         members = [\"a\"]\n\
+         [workspace.metadata.fetch-source]\n\
+         foo = { git = \"https://www.example.com/foo.git\" }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("a/Cargo.toml"),
+        "[package.metadata.fetch-source]\n\
+         bar = { git = \"https://www.example.com/bar.git\" }\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args([
+        "check",
+        "--workspace",
+        "-D",
+        "floating-reference",
+        "--manifest-file",
+    ])
+    .arg(root.join("a/Cargo.toml"));
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("checked 2 source(s)"))
+        .stderr(predicate::str::contains(format!(
+            "{}:4:1",
+            root.join("Cargo.toml").display()
+        )))
+        .stderr(predicate::str::contains(format!(
+            "{}:2:1",
+            root.join("a/Cargo.toml").display()
+        )));
+}
+
+#[test]
+fn test_cached_command_package_requires_manifest() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::cargo_bin("cargo-fetch-source").unwrap();
+    cmd.args(["cached", "-p", "foo", "--cache"])
+        .arg(temp_dir.path());
+    cmd.assert()
+        .code(2)
+        .stderr(predicate::str::contains("require --manifest-file"));
+}
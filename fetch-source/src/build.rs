@@ -0,0 +1,423 @@
+//! Helpers for fetching sources from a build script.
+//!
+//! [`Build`] reads the sources declared in the manifest of the crate being built, fetches any
+//! which aren't already cached and tells cargo when the build script should be re-run:
+//!
+//! ```no_run
+//! // build.rs
+//! fn main() -> Result<(), fetch_source::Error> {
+//!     let sources = fetch_source::build::Build::new().fetch()?;
+//!     let zlib = &sources["zlib"];
+//!     println!("cargo:rustc-link-search={}", zlib.display());
+//!     Ok(())
+//! }
+//! ```
+//!
+//! With [`Build::generate_module`], the paths are also written as constants to a [`MODULE_FILE`]
+//! in `OUT_DIR` which can be included in the crate:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/fetch_sources.rs"));
+//!
+//! fn zlib_header() -> std::path::PathBuf {
+//!     std::path::Path::new(ZLIB).join("zlib.h")
+//! }
+//! ```
+//!
+//! With [`Build::rustc_env`], each path is instead passed to the compiler as an environment
+//! variable named [`RUSTC_ENV_PREFIX`] followed by the identifier, for use with `env!`:
+//!
+//! ```ignore
+//! const ZLIB: &str = env!("FETCH_SOURCE_ZLIB");
+//! ```
+//!
+//! Source names are converted to identifiers with [`identifier`].
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{Cache, Conditions, Error, SourceName, SourcesTable};
+
+/// The environment variable giving the shared cache directory
+pub const CACHE_ENV: &str = "CARGO_FETCH_SOURCE_CACHE";
+
+/// The environment variable giving a list of read-only cache directories
+pub const READ_ONLY_CACHE_ENV: &str = "CARGO_FETCH_SOURCE_READ_ONLY_CACHE";
+
+/// The name of the cache directory created in `OUT_DIR` when no shared cache is configured
+pub const OUT_DIR_CACHE: &str = "fetch-source";
+
+/// The name of the generated module written to `OUT_DIR` by [`Build::generate_module`]
+pub const MODULE_FILE: &str = "fetch_sources.rs";
+
+/// The prefix of the environment variables set by [`Build::rustc_env`]
+pub const RUSTC_ENV_PREFIX: &str = "FETCH_SOURCE_";
+
+/// The paths of fetched sources, keyed by name
+pub type SourcePaths = BTreeMap<SourceName, PathBuf>;
+
+/// Fetch the sources declared in a crate's manifest from its build script.
+///
+/// Sources are cached in, in order of preference:
+/// - the directory given to [`Build::cache_dir`];
+/// - the directory given by the [`CACHE_ENV`] environment variable;
+/// - a [`OUT_DIR_CACHE`] directory in `OUT_DIR`.
+///
+/// Any read-only caches listed in [`READ_ONLY_CACHE_ENV`] are searched first, and vendored copies
+/// of sources (see [`Vendor`](crate::Vendor)) are preferred over cached ones.
+///
+/// Sources which require features are only fetched if one of their features is enabled, and
+/// sources for particular platforms only if one of them matches the target, as given by the
+/// `CARGO_FEATURE_*`, `TARGET` and `CARGO_CFG_*` environment variables (see
+/// [`Conditions::from_env`]).
+#[derive(Debug, Default, Clone)]
+pub struct Build {
+    manifest_dir: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    generate_module: bool,
+    rustc_env: bool,
+    conditions: Option<Conditions>,
+}
+
+fn env_path(name: &str) -> Result<PathBuf, Error> {
+    std::env::var_os(name).map(PathBuf::from).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("environment variable '{name}' is not set, is this a build script?"),
+        )
+        .into()
+    })
+}
+
+impl Build {
+    /// Create a build helper using the environment cargo gives to build scripts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the manifest in this directory instead of `CARGO_MANIFEST_DIR`.
+    pub fn manifest_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.manifest_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Use this directory instead of `OUT_DIR`.
+    pub fn out_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.out_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Cache sources in this directory, which is created if needed.
+    pub fn cache_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Whether to write a [`MODULE_FILE`] to `OUT_DIR` with a `pub const NAME: &str` giving the
+    /// path of each source. Names are converted to identifiers with [`identifier`].
+    pub fn generate_module(&mut self, generate: bool) -> &mut Self {
+        self.generate_module = generate;
+        self
+    }
+
+    /// Decide which sources are needed with these conditions instead of reading them from the
+    /// environment.
+    pub fn conditions(&mut self, conditions: Conditions) -> &mut Self {
+        self.conditions = Some(conditions);
+        self
+    }
+
+    /// Whether to set an environment variable giving the path of each source when compiling the
+    /// crate, by printing `cargo:rustc-env=FETCH_SOURCE_<NAME>=<path>`. `<NAME>` is the source name
+    /// converted with [`identifier`].
+    pub fn rustc_env(&mut self, rustc_env: bool) -> &mut Self {
+        self.rustc_env = rustc_env;
+        self
+    }
+
+    /// Fetch all missing sources, print instructions for cargo, and return the path of every
+    /// source.
+    ///
+    /// Sources which couldn't be fetched are reported with `cargo:warning` and the first error is
+    /// returned.
+    pub fn fetch(&self) -> Result<SourcePaths, Error> {
+        self.fetch_with_output(&mut std::io::stdout().lock())
+    }
+
+    /// As [`Build::fetch`], writing the instructions for cargo to `out`.
+    pub(crate) fn fetch_with_output<W: Write>(&self, out: &mut W) -> Result<SourcePaths, Error> {
+        let manifest_dir = match &self.manifest_dir {
+            Some(dir) => dir.clone(),
+            None => env_path("CARGO_MANIFEST_DIR")?,
+        };
+        let manifest = manifest_dir.join("Cargo.toml");
+        writeln!(out, "cargo:rerun-if-changed={}", manifest.display())?;
+        writeln!(out, "cargo:rerun-if-env-changed={CACHE_ENV}")?;
+        writeln!(out, "cargo:rerun-if-env-changed={READ_ONLY_CACHE_ENV}")?;
+        let document = std::fs::read_to_string(&manifest)?;
+        let variables = crate::Variables::from_env();
+        let parsed = crate::try_parse_toml_with_variables(&document, &variables);
+        // Sources may refer to environment variables, even if they turn out to be undefined
+        for name in variables.env_vars_read() {
+            writeln!(out, "cargo:rerun-if-env-changed={name}")?;
+        }
+        let (mut sources, mut options) =
+            parsed.map_err(|err| crate::Diagnostic::new(err, &manifest, Some(&document)))?;
+        match &self.conditions {
+            Some(conditions) => conditions.retain(&mut sources, &mut options),
+            None => Conditions::from_env().retain(&mut sources, &mut options),
+        }
+
+        let mut paths = SourcePaths::new();
+        let sources = match crate::load_vendor(&manifest_dir)? {
+            Some(vendor) => {
+                writeln!(
+                    out,
+                    "cargo:rerun-if-changed={}",
+                    vendor.vendor_file().display()
+                )?;
+                let (vendored, missing) = vendor.partition(sources);
+                paths.extend(vendored);
+                missing
+            }
+            None => sources,
+        };
+
+        let mut cache = self.cache()?;
+        let errors = fetch_missing(&mut cache, sources.clone());
+        for (name, source) in &sources {
+            if let Some(path) = cache.access(source)? {
+                paths.insert(name.clone(), path.to_path_buf());
+            }
+        }
+        cache.save()?;
+
+        let mut errors = errors.into_iter();
+        match errors.next() {
+            Some((name, first)) => {
+                writeln!(
+                    out,
+                    "cargo:warning=failed to fetch source '{name}': {first}"
+                )?;
+                for (name, err) in errors {
+                    writeln!(out, "cargo:warning=failed to fetch source '{name}': {err}")?;
+                }
+                Err(first.into())
+            }
+            None => {
+                if self.generate_module {
+                    let module = generate_module(&paths)?;
+                    std::fs::write(self.resolve_out_dir()?.join(MODULE_FILE), module)?;
+                }
+                if self.rustc_env {
+                    for (ident, (_, path)) in identifiers(&paths)? {
+                        writeln!(out, "cargo:rustc-env={RUSTC_ENV_PREFIX}{ident}={path}")?;
+                    }
+                }
+                Ok(paths)
+            }
+        }
+    }
+
+    fn resolve_out_dir(&self) -> Result<PathBuf, Error> {
+        match &self.out_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => env_path("OUT_DIR"),
+        }
+    }
+
+    /// Load the cache to fetch sources into, layered over any read-only caches
+    fn cache(&self) -> Result<Cache, Error> {
+        let cache_dir = match (&self.cache_dir, std::env::var_os(CACHE_ENV)) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(dir)) => PathBuf::from(dir),
+            (None, None) => self.resolve_out_dir()?.join(OUT_DIR_CACHE),
+        };
+        std::fs::create_dir_all(&cache_dir)?;
+        let mut cache = Cache::load_or_create(&cache_dir)?;
+        if let Some(dirs) = std::env::var_os(READ_ONLY_CACHE_ENV) {
+            for dir in std::env::split_paths(&dirs) {
+                cache.add_read_only(dir)?;
+            }
+        }
+        Ok(cache)
+    }
+}
+
+/// Convert a source name into an upper-case identifier, e.g. `syn::latest` becomes `SYN_LATEST`.
+///
+/// Each `::` separator and every other character which isn't an ASCII letter or digit becomes `_`,
+/// and letters are upper-cased. If the result would start with a digit it is prefixed with `_`.
+pub fn identifier<S: AsRef<str>>(name: S) -> String {
+    let mut ident = name
+        .as_ref()
+        .split("::")
+        .map(|part| {
+            part.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("_");
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+/// Pair each source name and path with its identifier, failing if two names map onto the same
+/// identifier or a path isn't valid UTF-8
+fn identifiers(paths: &SourcePaths) -> Result<BTreeMap<String, (&str, &str)>, Error> {
+    let mut identifiers = BTreeMap::new();
+    for (name, path) in paths {
+        let path = path.to_str().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("path of source '{name}' is not valid UTF-8"),
+            )
+        })?;
+        if let Some((other, _)) = identifiers.insert(identifier(name), (name.as_str(), path)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "sources '{other}' and '{name}' have the same identifier '{}'",
+                    identifier(name)
+                ),
+            )
+            .into());
+        }
+    }
+    Ok(identifiers)
+}
+
+/// The contents of the generated module with a constant for each path
+fn generate_module(paths: &SourcePaths) -> Result<String, Error> {
+    let mut module = String::from("// @generated by fetch-source. Do not edit.\n");
+    for (ident, (name, path)) in identifiers(paths)? {
+        // Names may contain any character, so escape them rather than writing a `///` comment
+        let doc = format!("The path of the `{name}` source");
+        module.push_str(&format!(
+            "\n#[doc = {doc:?}]\npub const {ident}: &str = {path:?};\n"
+        ));
+    }
+    Ok(module)
+}
+
+#[cfg(feature = "rayon")]
+fn fetch_missing(cache: &mut Cache, sources: SourcesTable) -> Vec<(SourceName, crate::FetchError)> {
+    crate::cache_all_par(cache, sources)
+}
+
+#[cfg(not(feature = "rayon"))]
+fn fetch_missing(cache: &mut Cache, sources: SourcesTable) -> Vec<(SourceName, crate::FetchError)> {
+    let mut errors = Vec::new();
+    for (name, source) in sources {
+        if cache.contains(&source) {
+            continue;
+        }
+        let artefact_dir = cache
+            .cache_dir()
+            .append(cache.items().relative_path(&source));
+        match source.fetch(&*artefact_dir) {
+            Ok(artefact) => cache.items_mut().insert(artefact),
+            Err(err) => errors.push((name, err)),
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Artefact, Source};
+
+    #[test]
+    fn fetch_returns_cached_paths_and_rerun_instructions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+            [package.metadata.fetch-source]
+            "foo::bar" = { git = "https://example.com/does-not-exist.git" }
+            disabled = { git = "https://example.com/${CARGO_PKG_NAME}.git", features = ["bundled"] }
+            "#,
+        )
+        .unwrap();
+        let cache_dir = dir.path().join("cache");
+        std::fs::create_dir(&cache_dir).unwrap();
+        let source = Source::git("https://example.com/does-not-exist.git", None, false);
+        let mut cache = Cache::new(&cache_dir).unwrap();
+        let artefact_dir = cache.artefact_path(&source);
+        std::fs::create_dir(&*artefact_dir).unwrap();
+        cache
+            .items_mut()
+            .insert(Artefact::new(source, artefact_dir.to_path_buf()));
+        cache.save().unwrap();
+
+        let mut out = Vec::new();
+        let paths = Build::new()
+            .manifest_dir(dir.path())
+            .cache_dir(&cache_dir)
+            .rustc_env(true)
+            .conditions(Conditions::new())
+            .fetch_with_output(&mut out)
+            .unwrap();
+        assert_eq!(paths["foo::bar"], *artefact_dir);
+        assert!(!paths.contains_key("disabled"));
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!(
+            "cargo:rerun-if-changed={}",
+            dir.path().join("Cargo.toml").display()
+        )));
+        assert!(out.contains("cargo:rerun-if-env-changed=CARGO_FETCH_SOURCE_CACHE"));
+        assert!(out.contains("cargo:rerun-if-env-changed=CARGO_PKG_NAME"));
+        assert!(out.contains(&format!(
+            "cargo:rustc-env=FETCH_SOURCE_FOO_BAR={}",
+            artefact_dir.display()
+        )));
+    }
+
+    #[test]
+    fn identifiers_are_mangled_names() {
+        assert_eq!(identifier("syn::latest"), "SYN_LATEST");
+        assert_eq!(identifier("syn::1.0.0"), "SYN_1_0_0");
+        assert_eq!(identifier("my-data"), "MY_DATA");
+        assert_eq!(identifier("1.0"), "_1_0");
+    }
+
+    #[test]
+    fn generated_module_has_a_constant_per_source() {
+        let mut paths = SourcePaths::new();
+        paths.insert("syn::latest".into(), PathBuf::from("/cache/abc"));
+        paths.insert("with\"quote".into(), PathBuf::from("/cache/d\"ef"));
+        let module = generate_module(&paths).unwrap();
+        assert!(module.contains("pub const SYN_LATEST: &str = \"/cache/abc\";"));
+        assert!(module.contains("pub const WITH_QUOTE: &str = \"/cache/d\\\"ef\";"));
+    }
+
+    #[test]
+    fn generated_docs_escape_names() {
+        let mut paths = SourcePaths::new();
+        paths.insert("a\nb".into(), PathBuf::from("/cache/abc"));
+        let module = generate_module(&paths).unwrap();
+        assert!(module.contains("#[doc = \"The path of the `a\\nb` source\"]\npub const A_B"));
+        assert!(!module.contains("\nb` source"));
+    }
+
+    #[test]
+    fn colliding_identifiers_fail() {
+        let mut paths = SourcePaths::new();
+        paths.insert("foo-bar".into(), PathBuf::from("/cache/a"));
+        paths.insert("foo_bar".into(), PathBuf::from("/cache/b"));
+        assert!(generate_module(&paths).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Support for exporting cached artefacts to, and importing them from, a single bundle file.
+//!
+//! A bundle is a gzipped tar archive. The first entry is an index of the bundled artefacts in the
+//! same format as a cache file, with paths relative to the root of the archive. Each artefact's
+//! directory follows, stored under its path relative to the cache root.
+
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cache::{CACHE_FILE_NAME, remove_path};
+use crate::{Cache, CacheItems, Source};
+
+/// The directory in the cache root which bundles are unpacked into before their artefacts are
+/// moved into place
+const STAGING_DIR_NAME: &str = "bundle.importing";
+
+fn invalid_bundle(msg: &str) -> crate::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid bundle: {msg}"),
+    )
+    .into()
+}
+
+/// Write a bundle containing the cached artefacts for `sources` to `writer`. Sources may be cached
+/// in the writable cache or any of its read-only caches. Returns the number of artefacts written.
+///
+/// The artefacts are retrieved with [`Cache::access`], so packed artefacts are unpacked and their
+/// use is recorded. Save the cache afterwards to keep this record.
+///
+/// Returns an error if any of the sources is not cached, or if an I/O or serialisation error
+/// occurs.
+pub fn export_bundle<W, I, S>(
+    cache: &mut Cache,
+    sources: I,
+    writer: W,
+) -> Result<usize, crate::Error>
+where
+    W: Write,
+    I: IntoIterator<Item = S>,
+    S: AsRef<Source>,
+{
+    let mut index = CacheItems::new();
+    let mut artefact_dirs = Vec::new();
+    for source in sources {
+        let source = source.as_ref();
+        if index.contains(source) {
+            continue;
+        }
+        let Some(artefact_dir) = cache.access(source)? else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("source is not cached: {source}"),
+            )
+            .into());
+        };
+        let relative_path = PathBuf::from(&*index.relative_path(source));
+        artefact_dirs.push((artefact_dir, relative_path.clone()));
+        // SAFETY: `access` found the source in the cache above
+        let artefact = cache.get(source).unwrap().clone();
+        index.insert(artefact.relocate(relative_path));
+    }
+
+    let json = serde_json::to_vec_pretty(&index)?;
+    let mut header = ::tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut builder = ::tar::Builder::new(encoder);
+    // Preserve any symlinks within the artefacts rather than archiving their targets
+    builder.follow_symlinks(false);
+    builder.append_data(&mut header, CACHE_FILE_NAME, json.as_slice())?;
+    for (artefact_dir, relative_path) in artefact_dirs {
+        builder.append_dir_all(&relative_path, &*artefact_dir)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(index.len())
+}
+
+/// Read a bundle from `reader` and add its artefacts to the writable cache. Artefacts which are
+/// already in the cache (or any of its read-only caches) are skipped, as are artefacts listed in
+/// the bundle's index without a directory. Returns the number of artefacts imported.
+///
+/// The bundle is unpacked into a staging directory in the cache root, and its artefacts are only
+/// moved into place once the whole bundle has been read.
+///
+/// Note that the cache is not saved.
+///
+/// Returns an error if the bundle is malformed, or if an I/O or deserialisation error occurs.
+pub fn import_bundle<R>(cache: &mut Cache, reader: R) -> Result<usize, crate::Error>
+where
+    R: Read,
+{
+    let mut archive = ::tar::Archive::new(flate2::read::GzDecoder::new(reader));
+    let mut entries = archive.entries()?;
+    let index: CacheItems = match entries.next() {
+        Some(entry) => {
+            let entry = entry?;
+            if *entry.path()? != *Path::new(CACHE_FILE_NAME) {
+                return Err(invalid_bundle("expected index as first entry"));
+            }
+            serde_json::from_reader(entry)?
+        }
+        None => return Err(invalid_bundle("bundle is empty")),
+    };
+
+    // Only unpack the directories of artefacts missing from the cache
+    let missing = index
+        .values()
+        .filter(|artefact| !cache.contains(artefact.source()))
+        .map(|artefact| PathBuf::from(&*index.relative_path(artefact)))
+        .collect::<BTreeSet<_>>();
+    // Unpack into a staging directory so that a truncated or corrupt bundle leaves nothing
+    // behind in the cache
+    let cache_root = cache.cache_dir();
+    let staging = cache_root.join(STAGING_DIR_NAME);
+    remove_path(&staging)?;
+    std::fs::create_dir_all(&staging)?;
+    let unpacked = match unpack_missing(entries, &missing, &staging) {
+        Ok(unpacked) => unpacked,
+        Err(err) => {
+            // Don't mask the unpacking error with a failure to clean up
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+    };
+
+    // Only record the artefacts whose directories were in the bundle, once they are in place
+    let items = cache.items_mut();
+    for (_, artefact) in index {
+        let relative_path = items.relative_path(&artefact);
+        if unpacked.contains(&*relative_path) {
+            let staged = staging.join(&*relative_path);
+            let path = cache_root.append(relative_path);
+            remove_path(&path)?;
+            std::fs::rename(staged, &*path)?;
+            items.insert(artefact.relocate(path.to_path_buf()));
+        }
+    }
+    std::fs::remove_dir_all(&staging)?;
+    Ok(unpacked.len())
+}
+
+/// Unpack the entries of the artefact directories in `missing` into `staging`, returning the
+/// directories which were found
+fn unpack_missing<R: Read>(
+    entries: ::tar::Entries<'_, R>,
+    missing: &BTreeSet<PathBuf>,
+    staging: &Path,
+) -> Result<BTreeSet<PathBuf>, crate::Error> {
+    let mut unpacked = BTreeSet::new();
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?;
+        let Some(first) = path.components().next() else {
+            continue;
+        };
+        let artefact_path = PathBuf::from(first.as_os_str());
+        if missing.contains(&artefact_path) {
+            // `unpack_in` refuses to write outside of the staging directory
+            entry.unpack_in(staging)?;
+            unpacked.insert(artefact_path);
+        }
+    }
+    Ok(unpacked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Artefact;
+    use tempfile::tempdir;
+
+    /// Create a cache in `dir` containing an artefact directory for `source`
+    fn cache_with_source(dir: &Path, source: &Source) -> Cache {
+        let mut cache = Cache::new(dir).unwrap();
+        let artefact_dir = cache.artefact_path(source);
+        std::fs::create_dir_all(artefact_dir.join("src")).unwrap();
+        std::fs::write(artefact_dir.join("src/lib.rs"), "// cached").unwrap();
+        let artefact = Artefact::new(source.clone(), artefact_dir.to_path_buf());
+        cache.items_mut().insert(artefact);
+        cache
+    }
+
+    #[test]
+    fn export_import_roundtrip() {
+        let src_dir = tempdir().unwrap();
+        let dst_dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut src_cache = cache_with_source(src_dir.path(), &source);
+
+        let mut bundle = Vec::new();
+        assert_eq!(
+            export_bundle(&mut src_cache, [&source], &mut bundle).unwrap(),
+            1
+        );
+
+        let mut dst_cache = Cache::new(dst_dir.path()).unwrap();
+        assert_eq!(import_bundle(&mut dst_cache, bundle.as_slice()).unwrap(), 1);
+        assert!(dst_cache.items().contains(&source));
+        let artefact = dst_cache.items().get(&source).unwrap();
+        assert_eq!(artefact.path(), &*dst_cache.artefact_path(&source));
+        assert_eq!(
+            std::fs::read_to_string(artefact.path().join("src/lib.rs")).unwrap(),
+            "// cached"
+        );
+    }
+
+    #[cfg(feature = "pack")]
+    #[test]
+    fn export_unpacks_packed_artefacts() {
+        let src_dir = tempdir().unwrap();
+        let dst_dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut src_cache = cache_with_source(src_dir.path(), &source);
+        src_cache.pack_unused(std::time::Duration::ZERO).unwrap();
+        assert!(src_cache.is_packed(&source));
+
+        let mut bundle = Vec::new();
+        export_bundle(&mut src_cache, [&source], &mut bundle).unwrap();
+        assert!(!src_cache.is_packed(&source));
+
+        let mut dst_cache = Cache::new(dst_dir.path()).unwrap();
+        assert_eq!(import_bundle(&mut dst_cache, bundle.as_slice()).unwrap(), 1);
+        let lib_rs = dst_cache.artefact_path(&source).join("src/lib.rs");
+        assert_eq!(std::fs::read_to_string(lib_rs).unwrap(), "// cached");
+    }
+
+    #[test]
+    fn import_skips_cached_artefacts() {
+        let src_dir = tempdir().unwrap();
+        let dst_dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut src_cache = cache_with_source(src_dir.path(), &source);
+        let mut bundle = Vec::new();
+        export_bundle(&mut src_cache, [&source], &mut bundle).unwrap();
+
+        let mut dst_cache = cache_with_source(dst_dir.path(), &source);
+        let lib_rs = dst_cache.artefact_path(&source).join("src/lib.rs");
+        std::fs::write(&lib_rs, "// already here").unwrap();
+        assert_eq!(import_bundle(&mut dst_cache, bundle.as_slice()).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(lib_rs).unwrap(), "// already here");
+    }
+
+    #[test]
+    fn import_skips_indexed_artefacts_without_directories() {
+        let dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut index = CacheItems::new();
+        let relative_path = PathBuf::from(&*index.relative_path(&source));
+        index.insert(Artefact::new(source.clone(), relative_path));
+        let json = serde_json::to_vec(&index).unwrap();
+        let mut header = ::tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = ::tar::Builder::new(encoder);
+        builder
+            .append_data(&mut header, CACHE_FILE_NAME, json.as_slice())
+            .unwrap();
+        let bundle = builder.into_inner().unwrap().finish().unwrap();
+
+        let mut cache = Cache::new(dir.path()).unwrap();
+        assert_eq!(import_bundle(&mut cache, bundle.as_slice()).unwrap(), 0);
+        assert!(!cache.items().contains(&source));
+    }
+
+    #[test]
+    fn truncated_bundle_leaves_cache_unchanged() {
+        let src_dir = tempdir().unwrap();
+        let dst_dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut src_cache = cache_with_source(src_dir.path(), &source);
+        // Incompressible contents, so that truncating the bundle cuts through them
+        let mut state = 1u32;
+        let noise = (0..1 << 16)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        let artefact_dir = src_cache.artefact_path(&source);
+        std::fs::write(artefact_dir.join("src/noise.bin"), noise).unwrap();
+        let mut bundle = Vec::new();
+        export_bundle(&mut src_cache, [&source], &mut bundle).unwrap();
+        bundle.truncate(bundle.len() / 2);
+
+        let mut dst_cache = Cache::new(dst_dir.path()).unwrap();
+        assert!(import_bundle(&mut dst_cache, bundle.as_slice()).is_err());
+        assert!(!dst_cache.items().contains(&source));
+        assert!(!dst_cache.artefact_path(&source).exists());
+        assert!(!dst_dir.path().join(STAGING_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn export_uncached_source_fails() {
+        let dir = tempdir().unwrap();
+        let mut cache = Cache::new(dir.path()).unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let result = export_bundle(&mut cache, [&source], Vec::new());
+        assert_eq!(result.unwrap_err().kind(), &crate::ErrorKind::Io);
+    }
+
+    #[test]
+    fn import_garbage_fails() {
+        let dir = tempdir().unwrap();
+        let mut cache = Cache::new(dir.path()).unwrap();
+        assert!(import_bundle(&mut cache, b"not a bundle".as_slice()).is_err());
+    }
+}
@@ -4,9 +4,10 @@ use std::path::{Path, PathBuf};
 
 use derive_more::Deref;
 
+use crate::objects::{self, DedupStats, OBJECTS_DIR};
 use crate::{Artefact, Digest, Source};
 
-const CACHE_FILE_NAME: &str = "fetch-source-cache.json";
+pub(crate) const CACHE_FILE_NAME: &str = "fetch-source-cache.json";
 
 /***
 NOTE: For the special path newtypes below, we derive `Deref` as this models these types as "subtypes" of
@@ -17,7 +18,7 @@ where their special meaning to the cache matters.
 ***/
 
 /// The root directory of a cache
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deref)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Deref)]
 pub struct CacheRoot(PathBuf);
 
 /// The path of a cached artefact relative to the cache root
@@ -25,7 +26,7 @@ pub struct CacheRoot(PathBuf);
 pub struct RelCacheDir(PathBuf);
 
 /// The absolute path to a cached artefact
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deref)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deref)]
 pub struct CacheDir(PathBuf);
 
 impl CacheRoot {
@@ -35,6 +36,13 @@ impl CacheRoot {
     }
 }
 
+impl CacheDir {
+    /// The path of the compressed archive holding this artefact while it is packed.
+    fn packed_path(&self) -> PathBuf {
+        self.0.with_extension("tar.gz")
+    }
+}
+
 /// Records data about the cached sources and where their artefacts are within a [`Cache`](Cache).
 ///
 /// When a [`Source`] is fetched, insert its [`Artefact`] into a cache to avoid repeatedly fetching
@@ -65,6 +73,10 @@ impl CacheItems {
         self.map.contains_key(&Source::digest(source))
     }
 
+    fn get_mut(&mut self, source: &Source) -> Option<&mut Artefact> {
+        self.map.get_mut(&Source::digest(source))
+    }
+
     /// Cache an artefact and return the digest of the [`Source`] which created it. Replaces any
     /// previous value for this source.
     pub fn insert(&mut self, artefact: Artefact) {
@@ -95,13 +107,56 @@ impl CacheItems {
     pub fn relative_path<S: AsRef<Source>>(&self, source: S) -> RelCacheDir {
         RelCacheDir(PathBuf::from(Source::digest(source).as_ref()))
     }
+
+    /// Whether merging would replace any artefact for the same source with `artefact`, i.e. if no
+    /// artefact for the source is cached or `artefact` was fetched more recently.
+    fn prefers(&self, artefact: &Artefact) -> bool {
+        match self.get(artefact.source()) {
+            Some(existing) => match (existing.fetched(), artefact.fetched()) {
+                (Some(existing), Some(incoming)) => incoming > existing,
+                _ => false,
+            },
+            None => true,
+        }
+    }
+
+    /// Merge the artefacts in `other` into this collection. Artefacts for sources missing from
+    /// this collection are added. Where both collections contain an artefact for the same source,
+    /// the most recently fetched one is kept. If either fetch time is unknown, the existing
+    /// artefact is kept.
+    ///
+    /// Returns the digests of the sources whose artefacts were taken from `other`. Note that the
+    /// paths of these artefacts are not changed.
+    pub fn merge(&mut self, other: CacheItems) -> Vec<Digest> {
+        let mut merged = Vec::new();
+        for (digest, artefact) in other {
+            if self.prefers(&artefact) {
+                self.insert(artefact);
+                merged.push(digest);
+            }
+        }
+        merged
+    }
+}
+
+/// A cache which is consulted when looking up sources but never written to, such as a shared,
+/// pre-populated cache directory.
+#[derive(Debug)]
+struct ReadOnlyLayer {
+    items: CacheItems,
+    root: CacheRoot,
 }
 
 /// Owns [`data`](CacheItems) about cached sources and is responsible for its persistence.
+///
+/// A cache may also be layered over any number of read-only caches (see [`Cache::add_read_only`]).
+/// These are searched in the order they were added, before the writable cache, when looking up a
+/// source. Only the writable cache is ever modified or saved.
 #[derive(Debug)]
 pub struct Cache {
     items: CacheItems,
     cache_file: PathBuf,
+    read_only: Vec<ReadOnlyLayer>,
 }
 
 impl Cache {
@@ -126,9 +181,21 @@ impl Cache {
         Self {
             items: CacheItems::new(),
             cache_file,
+            read_only: Vec::new(),
         }
     }
 
+    /// Read the cache items from the cache file in `cache_dir`.
+    fn read_items<P>(cache_dir: P) -> Result<(CacheItems, PathBuf), crate::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let cache_file = Self::normalise_cache_file(cache_dir)?;
+        let contents = std::fs::read_to_string(&cache_file)?;
+        let items: CacheItems = serde_json::from_str(&contents)?;
+        Ok((items, cache_file))
+    }
+
     /// Read the cache in the given directory.
     ///
     /// Error if the directory or cache file do not exist, of if a deserialisation error occurs
@@ -137,10 +204,12 @@ impl Cache {
     where
         P: AsRef<Path>,
     {
-        let cache_file = Self::normalise_cache_file(cache_dir)?;
-        let contents = std::fs::read_to_string(&cache_file)?;
-        let items: CacheItems = serde_json::from_str(&contents)?;
-        Ok(Self { items, cache_file })
+        let (items, cache_file) = Self::read_items(cache_dir)?;
+        Ok(Self {
+            items,
+            cache_file,
+            read_only: Vec::new(),
+        })
     }
 
     /// Create a new cache in the given directory.
@@ -179,7 +248,46 @@ impl Cache {
         }
     }
 
-    /// Saves the cache in the directory where it was created.
+    /// Add a read-only cache in the given directory. Read-only caches are searched in the order they
+    /// were added, before this cache, when looking up a source. They are never modified or saved.
+    ///
+    /// Error if the directory or cache file do not exist, or if a deserialisation error occurs
+    /// when reading the cache file.
+    pub fn add_read_only<P>(&mut self, cache_dir: P) -> Result<(), crate::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let (items, cache_file) = Self::read_items(cache_dir)?;
+        // SAFETY: `normalise_cache_file` always joins a file name onto the directory
+        let root = CacheRoot(cache_file.parent().unwrap().to_path_buf());
+        self.read_only.push(ReadOnlyLayer { items, root });
+        Ok(())
+    }
+
+    /// Get the root directories of the read-only caches in the order they are searched.
+    pub fn read_only_roots(&self) -> impl Iterator<Item = &CacheRoot> {
+        self.read_only.iter().map(|layer| &layer.root)
+    }
+
+    /// Find the cache layer containing `source`, searching read-only caches first.
+    fn find(&self, source: &Source) -> Option<(&Artefact, CacheRoot)> {
+        self.read_only
+            .iter()
+            .find_map(|layer| layer.items.get(source).map(|a| (a, layer.root.clone())))
+            .or_else(|| self.items.get(source).map(|a| (a, self.cache_dir())))
+    }
+
+    /// Check whether the source is cached in this cache or any read-only cache.
+    pub fn contains(&self, source: &Source) -> bool {
+        self.find(source).is_some()
+    }
+
+    /// Retrieves a cached artefact for the given source from this cache or any read-only cache.
+    pub fn get(&self, source: &Source) -> Option<&Artefact> {
+        self.find(source).map(|(artefact, _)| artefact)
+    }
+
+    /// Saves the cache in the directory where it was created. Read-only caches are not saved.
     ///
     /// Returns an error if a serialisation or I/O error occurs.
     pub fn save(&self) -> Result<(), crate::Error> {
@@ -187,6 +295,183 @@ impl Cache {
         Ok(std::fs::write(&self.cache_file, json)?)
     }
 
+    /// Merge the artefacts in the writable part of `other` into this cache, copying their
+    /// directories into this cache. Conflicts are resolved as in [`CacheItems::merge`]. Artefacts
+    /// whose files are missing from `other` are skipped. Returns the digests of the sources whose
+    /// artefacts were taken from `other`.
+    ///
+    /// Note that the cache is not saved.
+    ///
+    /// Returns an error if an artefact directory can't be copied. Artefacts merged before the error
+    /// occurred remain in this cache, and the artefact being copied is left unchanged.
+    pub fn merge(&mut self, other: Cache) -> Result<Vec<Digest>, crate::Error> {
+        let root = self.cache_dir();
+        let other_root = other.cache_dir();
+        let mut merged = Vec::new();
+        for (digest, artefact) in other.items {
+            if !self.items.prefers(&artefact) {
+                continue;
+            }
+            let from = other_root.append(self.items.relative_path(&artefact));
+            let to = root.append(self.items.relative_path(&artefact));
+            let packed = !from.exists() && from.packed_path().is_file();
+            if !packed && !from.is_dir() {
+                // Nothing to copy, so keep any artefact this cache already has
+                continue;
+            }
+            // Copy into a sibling first so a failed copy doesn't lose the artefact it replaces
+            let staging = to.with_extension("merging");
+            remove_path(&staging)?;
+            let copied = if packed {
+                std::fs::copy(from.packed_path(), &staging).map(|_| ())
+            } else {
+                crate::fs::copy_dir_all(&from, &staging)
+            };
+            if let Err(err) = copied {
+                // Don't mask the copy error with a failure to clean up
+                let _ = remove_path(&staging);
+                return Err(err.into());
+            }
+            remove_path(&to)?;
+            remove_path(&to.packed_path())?;
+            if packed {
+                std::fs::rename(&staging, to.packed_path())?;
+            } else {
+                std::fs::rename(&staging, &*to)?;
+            }
+            self.items.insert(artefact.relocate(to.0));
+            merged.push(digest);
+        }
+        Ok(merged)
+    }
+
+    /// Get the path to the cached artefact for `source` and record that it was accessed. If the
+    /// artefact was packed by [`Cache::pack_unused`], it is unpacked first. Returns `None` if the
+    /// source isn't cached.
+    ///
+    /// Artefacts in read-only caches are never packed, and accesses to them aren't recorded.
+    ///
+    /// Returns an error if the artefact can't be unpacked.
+    pub fn access(&mut self, source: &Source) -> Result<Option<CacheDir>, crate::Error> {
+        if !self.items.contains(source) {
+            return Ok(self.contains(source).then(|| self.artefact_path(source)));
+        }
+        let path = self.artefact_path(source);
+        #[cfg(feature = "pack")]
+        if self.is_packed(source) {
+            crate::pack::unpack(&path.packed_path(), &path)?;
+        }
+        if let Some(artefact) = self.items.get_mut(source) {
+            artefact.touch();
+        }
+        Ok(Some(path))
+    }
+
+    /// Whether the artefact for `source` in the writable cache is packed into a compressed archive.
+    /// Use [`Cache::access`] to unpack it.
+    pub fn is_packed(&self, source: &Source) -> bool {
+        if !self.items.contains(source) {
+            return false;
+        }
+        let path = self.cache_dir().append(self.items.relative_path(source));
+        !path.exists() && path.packed_path().is_file()
+    }
+
+    /// Remove the artefact for `source` from the writable cache and delete its files, including its
+    /// packed archive if it was packed. Read-only caches are never modified. Returns the removed
+    /// artefact, or `None` if the source isn't in the writable cache.
+    ///
+    /// Returns an error if the artefact's files can't be deleted, in which case it is kept.
+    pub fn evict(&mut self, source: &Source) -> Result<Option<Artefact>, crate::Error> {
+        if !self.items.contains(source) {
+            return Ok(None);
+        }
+        let path = self.cache_dir().append(self.items.relative_path(source));
+        if path.is_dir() {
+            std::fs::remove_dir_all(&*path)?;
+        }
+        let packed = path.packed_path();
+        if packed.is_file() {
+            std::fs::remove_file(packed)?;
+        }
+        Ok(self.items.remove(source))
+    }
+
+    /// Pack the artefacts in the writable cache which haven't been used (i.e. accessed through
+    /// [`Cache::access`] or fetched) for at least `unused_for` into compressed archives in the
+    /// cache directory. For artefacts with no record of when they were last used, e.g. in caches
+    /// written by older versions, the modification time of the artefact's directory is used
+    /// instead. Returns the number of artefacts packed.
+    ///
+    /// Packed artefacts remain in the cache and are unpacked on demand by [`Cache::access`].
+    ///
+    /// Returns an error if an artefact can't be packed. Artefacts packed before the error
+    /// occurred remain packed.
+    #[cfg(feature = "pack")]
+    pub fn pack_unused(&self, unused_for: std::time::Duration) -> Result<usize, crate::Error> {
+        let now = std::time::SystemTime::now();
+        let root = self.cache_dir();
+        let mut packed = 0;
+        for artefact in self.items.values() {
+            let path = root.append(self.items.relative_path(artefact));
+            if !path.is_dir() {
+                continue;
+            }
+            let last_used = match artefact.last_used() {
+                Some(last_used) => last_used,
+                None => std::fs::metadata(&*path)?.modified()?,
+            };
+            let is_unused = now
+                .duration_since(last_used)
+                .is_ok_and(|elapsed| elapsed >= unused_for);
+            if is_unused {
+                crate::pack::pack(&path, &path.packed_path())?;
+                packed += 1;
+            }
+        }
+        Ok(packed)
+    }
+
+    /// Deduplicate the files of the cached artefact for `source`. Each file is stored in a
+    /// content-addressed object store in the cache directory, and files which are identical to a
+    /// file in any previously-deduplicated artefact are replaced with a hard link to it.
+    ///
+    /// Note that files in a deduplicated artefact must not be modified in place, as this would
+    /// modify the same file in other artefacts.
+    ///
+    /// Returns an error if the source isn't in the writable cache, or if an I/O error occurs.
+    pub fn deduplicate(&self, source: &Source) -> Result<DedupStats, crate::Error> {
+        if !self.items.contains(source) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("source is not cached: {source}"),
+            )
+            .into());
+        }
+        let root = self.cache_dir();
+        let artefact_dir = root.append(self.items.relative_path(source));
+        Ok(objects::deduplicate(
+            &root.join(OBJECTS_DIR),
+            &artefact_dir,
+        )?)
+    }
+
+    /// Deduplicate every artefact in the writable cache as in [`Cache::deduplicate`], then remove
+    /// any objects which are no longer used by an artefact.
+    ///
+    /// Returns an error if an I/O error occurs.
+    pub fn deduplicate_all(&self) -> Result<DedupStats, crate::Error> {
+        let mut stats = DedupStats::default();
+        for artefact in self.items.values() {
+            // Packed artefacts have no files to deduplicate
+            if !self.is_packed(artefact.source()) {
+                stats += self.deduplicate(artefact.source())?;
+            }
+        }
+        objects::prune(&self.cache_dir().join(OBJECTS_DIR))?;
+        Ok(stats)
+    }
+
     /// Get the cache file path.
     pub fn cache_file(&self) -> &Path {
         &self.cache_file
@@ -197,9 +482,27 @@ impl Cache {
         CacheRoot(self.cache_file.parent().unwrap().to_path_buf())
     }
 
-    /// Calculate the absolute path where a fetched source would be stored within the cache
+    /// Calculate the absolute path where a fetched source is, or would be, stored within the cache.
+    /// If the source is already cached in a read-only cache, this is the path within that cache.
+    ///
+    /// The path is calculated without checking whether the artefact was packed by
+    /// [`Cache::pack_unused`], so it may not exist. Use [`Cache::access`] to get the path of a
+    /// cached artefact in order to use it.
+    pub fn artefact_path(&self, source: &Source) -> CacheDir {
+        let root = match self.find(source) {
+            Some((_, root)) => root,
+            None => self.cache_dir(),
+        };
+        root.append(self.items.relative_path(source))
+    }
+
+    /// Calculate the absolute path where a fetched source would be stored within the cache.
+    #[deprecated(
+        note = "the artefact may be packed, so use `Cache::access` to get the path of a cached \
+                artefact, or `Cache::artefact_path` for where a source is stored"
+    )]
     pub fn cached_path(&self, source: &Source) -> CacheDir {
-        self.cache_dir().append(self.items.relative_path(source))
+        self.artefact_path(source)
     }
 
     /// Get a reference to the cache items.
@@ -257,6 +560,17 @@ impl<'a> IntoIterator for &'a Cache {
     }
 }
 
+/// Remove a file or directory, if it exists
+pub(crate) fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,7 +590,7 @@ mod tests {
             crate::build_from_json! { "tar": "www.example.com/test.tar.gz" }.unwrap();
         assert_eq!(
             PathBuf::from("/foo/bar/").join(Source::digest(&source).as_ref()),
-            *cache.cached_path(&source)
+            *cache.artefact_path(&source)
         );
     }
 
@@ -322,6 +636,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn evict_removes_artefact_and_files() {
+        let temp_dir = tempdir().unwrap();
+        let mut cache = Cache::load_or_create(&temp_dir).unwrap();
+        let source = Source::git("https://www.example.com/foo.git", None, false);
+        let path = cache.artefact_path(&source);
+        std::fs::create_dir_all(path.join("src")).unwrap();
+        std::fs::write(path.join("src").join("lib.rs"), "").unwrap();
+        let artefact: Artefact = serde_json::from_value(serde_json::json!({
+            "source": source,
+            "path": &*path,
+        }))
+        .unwrap();
+        cache.items_mut().insert(artefact);
+
+        assert!(cache.evict(&source).unwrap().is_some());
+        assert!(!cache.contains(&source));
+        assert!(!path.exists());
+        assert!(cache.evict(&source).unwrap().is_none());
+    }
+
     #[test]
     fn cache_read_on_existing_dir_missing_file_fails() {
         let temp_dir = tempdir().unwrap();
@@ -371,6 +706,229 @@ mod tests {
         assert_eq!(result.unwrap_err().kind(), &crate::ErrorKind::Io);
     }
 
+    #[test]
+    fn read_only_cache_is_searched_first() {
+        let shared_dir = tempdir().unwrap();
+        let user_dir = tempdir().unwrap();
+        let source: Source = crate::build_from_json! { "git": "www.example.com/test.git" }.unwrap();
+
+        let mut shared = Cache::new(&shared_dir).unwrap();
+        shared.items_mut().insert(
+            crate::build_from_json! {
+                "source": { "git": "www.example.com/test.git" },
+                "path": "/shared/path",
+            }
+            .unwrap(),
+        );
+        shared.save().unwrap();
+
+        let mut cache = Cache::load_or_create(&user_dir).unwrap();
+        assert!(!cache.contains(&source));
+        cache.add_read_only(&shared_dir).unwrap();
+        assert!(cache.contains(&source));
+        assert!(cache.items().is_empty(), "Read-only items aren't writable");
+        assert_eq!(
+            cache.get(&source).unwrap().path(),
+            Path::new("/shared/path")
+        );
+        assert_eq!(
+            *cache.artefact_path(&source),
+            shared_dir
+                .path()
+                .canonicalize()
+                .unwrap()
+                .join(Source::digest(&source).as_ref())
+        );
+    }
+
+    #[test]
+    fn read_only_cache_is_not_saved() {
+        let shared_dir = tempdir().unwrap();
+        let user_dir = tempdir().unwrap();
+        let mut shared = Cache::new(&shared_dir).unwrap();
+        shared.items_mut().insert(
+            crate::build_from_json! {
+                "source": { "git": "www.example.com/test.git" },
+                "path": "/shared/path",
+            }
+            .unwrap(),
+        );
+        shared.save().unwrap();
+
+        let mut cache = Cache::load_or_create(&user_dir).unwrap();
+        cache.add_read_only(&shared_dir).unwrap();
+        cache.save().unwrap();
+        assert!(Cache::read(&user_dir).unwrap().items().is_empty());
+    }
+
+    #[test]
+    fn read_only_cache_must_exist() {
+        let temp_dir = tempdir().unwrap();
+        let mut cache = mock_cache_at! {"/foo/bar"};
+        assert!(cache.add_read_only(&temp_dir).is_err());
+    }
+
+    #[test]
+    fn merge_keeps_most_recently_fetched() {
+        let artefact = |path: &str, fetched: Option<u64>| -> Artefact {
+            crate::build_from_json! {
+                "source": { "git": "www.example.com/test.git" },
+                "path": path,
+                "fetched": fetched,
+            }
+            .unwrap()
+        };
+        let source = artefact("", None).source().clone();
+
+        let mut items = CacheItems::new();
+        items.insert(artefact("/old", Some(100)));
+        let mut newer = CacheItems::new();
+        newer.insert(artefact("/new", Some(200)));
+        assert_eq!(items.merge(newer), vec![Source::digest(&source)]);
+        assert_eq!(items.get(&source).unwrap().path(), Path::new("/new"));
+
+        let mut older = CacheItems::new();
+        older.insert(artefact("/older", Some(50)));
+        assert!(items.merge(older).is_empty());
+
+        let mut unknown = CacheItems::new();
+        unknown.insert(artefact("/unknown", None));
+        assert!(items.merge(unknown).is_empty());
+        assert_eq!(items.get(&source).unwrap().path(), Path::new("/new"));
+    }
+
+    #[test]
+    fn merge_copies_missing_artefact_directories() {
+        let src_dir = tempdir().unwrap();
+        let dst_dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut other = Cache::new(&src_dir).unwrap();
+        let artefact_dir = other.artefact_path(&source);
+        std::fs::create_dir_all(artefact_dir.join("src")).unwrap();
+        std::fs::write(artefact_dir.join("src/lib.rs"), "// merged").unwrap();
+        other
+            .items_mut()
+            .insert(Artefact::new(source.clone(), artefact_dir.to_path_buf()));
+
+        let mut cache = Cache::new(&dst_dir).unwrap();
+        let merged = cache.merge(other).unwrap();
+        assert_eq!(merged, vec![Source::digest(&source)]);
+        let artefact = cache.items().get(&source).unwrap();
+        assert_eq!(artefact.path(), &*cache.artefact_path(&source));
+        assert_eq!(
+            std::fs::read_to_string(artefact.path().join("src/lib.rs")).unwrap(),
+            "// merged"
+        );
+    }
+
+    #[test]
+    fn merge_skips_artefacts_with_missing_files() {
+        let src_dir = tempdir().unwrap();
+        let dst_dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let artefact = |cache: &Cache, fetched: u64| -> Artefact {
+            crate::build_from_json! {
+                "source": source,
+                "path": &*cache.artefact_path(&source),
+                "fetched": fetched,
+            }
+            .unwrap()
+        };
+
+        let mut cache = Cache::new(&dst_dir).unwrap();
+        let artefact_dir = cache.artefact_path(&source);
+        std::fs::create_dir_all(&*artefact_dir).unwrap();
+        std::fs::write(artefact_dir.join("lib.rs"), "// kept").unwrap();
+        let kept = artefact(&cache, 100);
+        cache.items_mut().insert(kept);
+
+        // The newer artefact in `other` has no files
+        let mut other = Cache::new(&src_dir).unwrap();
+        let newer = artefact(&other, 200);
+        other.items_mut().insert(newer);
+
+        assert!(cache.merge(other).unwrap().is_empty());
+        assert_eq!(
+            cache.items().get(&source).unwrap().fetched(),
+            Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(100))
+        );
+        assert_eq!(
+            std::fs::read_to_string(artefact_dir.join("lib.rs")).unwrap(),
+            "// kept"
+        );
+    }
+
+    #[test]
+    fn access_records_last_use() {
+        let dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut cache = Cache::new(&dir).unwrap();
+        assert_eq!(cache.access(&source).unwrap(), None);
+
+        let artefact_dir = cache.artefact_path(&source);
+        cache
+            .items_mut()
+            .insert(Artefact::new(source.clone(), artefact_dir.to_path_buf()));
+        assert_eq!(cache.items().get(&source).unwrap().last_used(), None);
+        assert_eq!(cache.access(&source).unwrap(), Some(artefact_dir));
+        assert!(cache.items().get(&source).unwrap().accessed().is_some());
+    }
+
+    #[cfg(feature = "pack")]
+    #[test]
+    fn unused_artefacts_are_packed_and_unpacked_on_access() {
+        let dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut cache = Cache::new(&dir).unwrap();
+        let artefact_dir = cache.artefact_path(&source);
+        std::fs::create_dir_all(artefact_dir.join("src")).unwrap();
+        std::fs::write(artefact_dir.join("src/lib.rs"), "// packed").unwrap();
+        cache
+            .items_mut()
+            .insert(Artefact::new(source.clone(), artefact_dir.to_path_buf()));
+
+        // Recently used artefacts aren't packed
+        cache.access(&source).unwrap();
+        let a_day = std::time::Duration::from_secs(24 * 60 * 60);
+        assert_eq!(cache.pack_unused(a_day).unwrap(), 0);
+        assert!(!cache.is_packed(&source));
+
+        assert_eq!(cache.pack_unused(std::time::Duration::ZERO).unwrap(), 1);
+        assert!(cache.is_packed(&source));
+        assert!(!artefact_dir.exists());
+        assert!(cache.contains(&source));
+
+        assert_eq!(cache.access(&source).unwrap(), Some(artefact_dir));
+        assert!(!cache.is_packed(&source));
+        let lib_rs = cache.artefact_path(&source).join("src/lib.rs");
+        assert_eq!(std::fs::read_to_string(lib_rs).unwrap(), "// packed");
+    }
+
+    #[cfg(feature = "pack")]
+    #[test]
+    fn artefacts_without_last_use_fall_back_to_modification_time() {
+        let dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        let mut cache = Cache::new(&dir).unwrap();
+        let artefact_dir = cache.artefact_path(&source);
+        std::fs::create_dir_all(&*artefact_dir).unwrap();
+        let artefact = Artefact::new(source.clone(), artefact_dir.to_path_buf());
+        assert_eq!(artefact.last_used(), None);
+        cache.items_mut().insert(artefact);
+
+        let a_day = std::time::Duration::from_secs(24 * 60 * 60);
+        assert_eq!(cache.pack_unused(a_day).unwrap(), 0);
+        assert!(!cache.is_packed(&source));
+
+        let two_days_ago = std::time::SystemTime::now() - 2 * a_day;
+        std::fs::File::open(&*artefact_dir)
+            .unwrap()
+            .set_modified(two_days_ago)
+            .unwrap();
+        assert_eq!(cache.pack_unused(a_day).unwrap(), 1);
+        assert!(cache.is_packed(&source));
+    }
+
     #[test]
     fn cache_load_save_roundtrip() {
         let temp_dir = std::env::temp_dir().join("cache_test_migration");
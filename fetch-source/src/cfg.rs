@@ -0,0 +1,269 @@
+//! Target platforms given as a target triple or a `cfg(...)` expression, as in cargo's
+//! `[target.'cfg(...)'.dependencies]` tables.
+
+use std::collections::BTreeSet;
+
+/// A configuration option of a target, e.g. `unix` or `target_os = "linux"`
+pub(crate) type Cfg = (String, Option<String>);
+
+/// The configuration options of a target, as printed by `rustc --print cfg`
+pub(crate) type Cfgs = BTreeSet<Cfg>;
+
+/// A platform a source is needed for: either a target triple, e.g. `x86_64-pc-windows-msvc`, or a
+/// `cfg(...)` expression, e.g. `cfg(target_os = "linux")`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    spec: String,
+    cfg: Option<CfgExpr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CfgExpr {
+    Cfg(Cfg),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+impl Platform {
+    /// Whether the target with the given triple and configuration options is this platform
+    pub(crate) fn matches(&self, triple: &str, cfgs: &Cfgs) -> bool {
+        match &self.cfg {
+            Some(cfg) => cfg.matches(cfgs),
+            None => self.spec == triple,
+        }
+    }
+}
+
+impl CfgExpr {
+    fn matches(&self, cfgs: &Cfgs) -> bool {
+        match self {
+            CfgExpr::Cfg(cfg) => cfgs.contains(cfg),
+            CfgExpr::All(exprs) => exprs.iter().all(|expr| expr.matches(cfgs)),
+            CfgExpr::Any(exprs) => exprs.iter().any(|expr| expr.matches(cfgs)),
+            CfgExpr::Not(expr) => !expr.matches(cfgs),
+        }
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+impl std::str::FromStr for Platform {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = spec.trim();
+        let cfg = match spec.strip_prefix("cfg(").and_then(|s| s.strip_suffix(')')) {
+            Some(expr) => {
+                let mut parser = Parser::new(expr);
+                let cfg = parser.expr()?;
+                parser.end()?;
+                Some(cfg)
+            }
+            None if spec.is_empty() || spec.contains(|c: char| c.is_whitespace() || c == '(') => {
+                return Err(format!("invalid target '{spec}'"));
+            }
+            None => None,
+        };
+        Ok(Self {
+            spec: spec.to_string(),
+            cfg,
+        })
+    }
+}
+
+/// Parse the output of `rustc --print cfg`, i.e. one `name` or `name="value"` per line
+pub(crate) fn parse_cfgs(output: &str) -> Cfgs {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.trim_matches('"').to_string())),
+            None => (line.to_string(), None),
+        })
+        .collect()
+}
+
+/// Deserialize a single platform or a list of platforms
+pub(crate) fn deserialize_platforms<'de, D>(deserializer: D) -> Result<Vec<Platform>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    let specs = match <OneOrMany as serde::Deserialize>::deserialize(deserializer)? {
+        OneOrMany::One(spec) => vec![spec],
+        OneOrMany::Many(specs) => specs,
+    };
+    specs
+        .iter()
+        .map(|spec| spec.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// A recursive-descent parser for the contents of `cfg(...)`
+struct Parser<'a> {
+    input: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.input = self.input.trim_start();
+    }
+
+    fn eat(&mut self, token: char) -> bool {
+        self.skip_whitespace();
+        match self.input.strip_prefix(token) {
+            Some(rest) => {
+                self.input = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{token}'")))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.input.chars().next() {
+            Some(c) => format!("expected {expected} in cfg expression, found '{c}'"),
+            None => format!("expected {expected} in cfg expression, found end of input"),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.input.is_empty() {
+            Ok(())
+        } else {
+            Err(self.unexpected("end of input"))
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, String> {
+        self.skip_whitespace();
+        let end = self
+            .input
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.input.len());
+        if end == 0 || self.input.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.unexpected("an identifier"));
+        }
+        let (ident, rest) = self.input.split_at(end);
+        self.input = rest;
+        Ok(ident)
+    }
+
+    fn string(&mut self) -> Result<&'a str, String> {
+        if !self.eat('"') {
+            return Err(self.unexpected("a string"));
+        }
+        let end = self
+            .input
+            .find('"')
+            .ok_or_else(|| "unterminated string in cfg expression".to_string())?;
+        let (value, rest) = self.input.split_at(end);
+        self.input = &rest[1..];
+        Ok(value)
+    }
+
+    fn expr(&mut self) -> Result<CfgExpr, String> {
+        let ident = self.ident()?;
+        match ident {
+            "all" | "any" => {
+                self.expect('(')?;
+                let mut exprs = Vec::new();
+                while !self.eat(')') {
+                    exprs.push(self.expr()?);
+                    if !self.eat(',') {
+                        self.expect(')')?;
+                        break;
+                    }
+                }
+                Ok(if ident == "all" {
+                    CfgExpr::All(exprs)
+                } else {
+                    CfgExpr::Any(exprs)
+                })
+            }
+            "not" => {
+                self.expect('(')?;
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(CfgExpr::Not(Box::new(expr)))
+            }
+            name if self.eat('=') => Ok(CfgExpr::Cfg((
+                name.to_string(),
+                Some(self.string()?.to_string()),
+            ))),
+            name => Ok(CfgExpr::Cfg((name.to_string(), None))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linux_cfgs() -> Cfgs {
+        parse_cfgs("unix\ntarget_os=\"linux\"\ntarget_arch=\"x86_64\"\n")
+    }
+
+    fn matches_linux(spec: &str) -> bool {
+        spec.parse::<Platform>()
+            .unwrap()
+            .matches("x86_64-unknown-linux-gnu", &linux_cfgs())
+    }
+
+    #[test]
+    fn cfg_expressions_are_evaluated() {
+        assert!(matches_linux("cfg(unix)"));
+        assert!(matches_linux(r#"cfg(target_os = "linux")"#));
+        assert!(!matches_linux(r#"cfg(target_os = "windows")"#));
+        assert!(matches_linux(
+            r#"cfg(all(unix, target_arch = "x86_64", not(windows)))"#
+        ));
+        assert!(matches_linux(r#"cfg(any(windows, target_os = "linux",))"#));
+        assert!(!matches_linux("cfg(any())"));
+        assert!(matches_linux("cfg(all())"));
+    }
+
+    #[test]
+    fn triples_are_compared() {
+        assert!(matches_linux("x86_64-unknown-linux-gnu"));
+        assert!(!matches_linux("x86_64-pc-windows-msvc"));
+    }
+
+    #[test]
+    fn invalid_platforms_fail() {
+        for spec in [
+            "cfg(",
+            "cfg()",
+            "cfg(target_os = linux)",
+            "cfg(all(unix)",
+            "cfg(unix windows)",
+            "not a triple",
+        ] {
+            assert!(spec.parse::<Platform>().is_err(), "{spec}");
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Decide which sources are needed by a particular build of a crate.
+//!
+//! A source may declare `features = ["bundled"]` so that it is only used when the crate is built
+//! with at least one of those cargo features enabled. Sources without `features` are always used.
+//!
+//! A source may also declare `target = "cfg(target_os = \"linux\")"`, or a list of target triples
+//! and `cfg(...)` expressions, so that it is only used when building for a matching target.
+
+use std::collections::BTreeSet;
+
+use crate::Error;
+use crate::cfg::{Cfgs, parse_cfgs};
+use crate::source::{SourceOptions, SourceOptionsTable, SourcesTable};
+
+/// The prefix of the environment variables cargo sets for each enabled feature when running a
+/// build script
+const FEATURE_ENV_PREFIX: &str = "CARGO_FEATURE_";
+
+/// The prefix of the environment variables cargo sets for each configuration option of the target
+/// when running a build script
+const CFG_ENV_PREFIX: &str = "CARGO_CFG_";
+
+/// The configuration of a build which conditional sources are evaluated against.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Conditions {
+    /// Enabled features, normalised as for `CARGO_FEATURE_<NAME>`
+    features: BTreeSet<String>,
+    /// Whether every feature is treated as enabled
+    all_features: bool,
+    /// The target triple and its configuration options, if building for a known target
+    target: Option<(String, Cfgs)>,
+}
+
+/// Normalise a feature name in the same way cargo does for `CARGO_FEATURE_<NAME>`, i.e. upper-cased
+/// with `-` replaced by `_`.
+fn normalise_feature<S: AsRef<str>>(name: S) -> String {
+    name.as_ref().to_uppercase().replace('-', "_")
+}
+
+impl Conditions {
+    /// Conditions with no features enabled, matching any target
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the enabled features from the `CARGO_FEATURE_*` environment variables cargo sets for
+    /// build scripts, and the target from `TARGET` and the `CARGO_CFG_*` variables.
+    pub fn from_env() -> Self {
+        let mut conditions = Self::new();
+        let mut cfgs = Cfgs::new();
+        let vars = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)));
+        for (key, value) in vars {
+            if let Some(feature) = key.strip_prefix(FEATURE_ENV_PREFIX) {
+                conditions.enable_feature(feature);
+            } else if let Some(name) = key.strip_prefix(CFG_ENV_PREFIX) {
+                let name = name.to_lowercase();
+                if value.is_empty() {
+                    cfgs.insert((name, None));
+                } else {
+                    // Options with several values, e.g. `target_feature`, are comma-separated
+                    for value in value.split(',') {
+                        cfgs.insert((name.clone(), Some(value.to_string())));
+                    }
+                }
+            }
+        }
+        if let Ok(triple) = std::env::var("TARGET") {
+            conditions.target(triple, cfgs);
+        }
+        conditions
+    }
+
+    /// Enable a feature. Feature names are compared as cargo compares them in `CARGO_FEATURE_*`
+    /// variables, so `bundled-zlib` and `BUNDLED_ZLIB` are the same feature.
+    pub fn enable_feature<S: AsRef<str>>(&mut self, feature: S) -> &mut Self {
+        self.features.insert(normalise_feature(feature));
+        self
+    }
+
+    /// Treat every feature as enabled, like cargo's `--all-features`.
+    pub fn all_features(&mut self) -> &mut Self {
+        self.all_features = true;
+        self
+    }
+
+    /// Only match sources for the target with this triple and configuration options, given as
+    /// `(name, value)` pairs, e.g. `("unix", None)` and `("target_os", Some("linux"))`.
+    pub fn target<S, I>(&mut self, triple: S, cfgs: I) -> &mut Self
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = (String, Option<String>)>,
+    {
+        self.target = Some((triple.into(), cfgs.into_iter().collect()));
+        self
+    }
+
+    /// Only match sources for the target with this triple, getting its configuration options from
+    /// `rustc --print cfg`. Uses the `rustc` given by the `RUSTC` environment variable if set,
+    /// otherwise `rustc` on `PATH`.
+    pub fn target_from_rustc<S: Into<String>>(&mut self, triple: S) -> Result<&mut Self, Error> {
+        let triple = triple.into();
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let output = std::process::Command::new(rustc)
+            .args(["--print", "cfg", "--target", &triple])
+            .output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "rustc exited with status {}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+            .into());
+        }
+        let cfgs = parse_cfgs(&String::from_utf8_lossy(&output.stdout));
+        Ok(self.target(triple, cfgs))
+    }
+
+    /// Whether a source with these options is needed, i.e. it requires no features or at least
+    /// one of its features is enabled, and it declares no target or one of its targets matches.
+    pub fn is_enabled(&self, options: &SourceOptions) -> bool {
+        let features_enabled = self.all_features
+            || options.features().is_empty()
+            || options
+                .features()
+                .iter()
+                .any(|feature| self.features.contains(&normalise_feature(feature)));
+        let target_matches = match &self.target {
+            Some((triple, cfgs)) => {
+                options.targets().is_empty()
+                    || options
+                        .targets()
+                        .iter()
+                        .any(|platform| platform.matches(triple, cfgs))
+            }
+            None => true,
+        };
+        features_enabled && target_matches
+    }
+
+    /// Remove the sources which aren't needed, and their options.
+    pub fn retain(&self, sources: &mut SourcesTable, options: &mut SourceOptionsTable) {
+        sources.retain(|name, _| {
+            options
+                .get(name)
+                .is_none_or(|options| self.is_enabled(options))
+        });
+        options.retain(|name, _| sources.contains_key(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCES: &str = r#"
+        [package.metadata.fetch-source]
+        always = { git = "git@github.com:foo/always.git" }
+        bundled = { git = "git@github.com:foo/bundled.git", features = ["bundled"] }
+        either = { git = "git@github.com:foo/either.git", features = ["static-lib", "other"] }
+        linux = { git = "git@github.com:foo/linux.git", target = 'cfg(target_os = "linux")' }
+        windows = { git = "git@github.com:foo/windows.git", target = ["x86_64-pc-windows-msvc", "cfg(windows)"] }
+    "#;
+
+    fn enabled_sources(conditions: &Conditions) -> Vec<String> {
+        let (mut sources, mut options) = crate::try_parse_toml_with_options(SOURCES).unwrap();
+        conditions.retain(&mut sources, &mut options);
+        assert_eq!(sources.len(), options.len());
+        let mut names = sources.into_keys().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn sources_without_features_are_always_enabled() {
+        assert_eq!(
+            enabled_sources(&Conditions::new()),
+            ["always", "linux", "windows"]
+        );
+    }
+
+    #[test]
+    fn sources_are_enabled_by_any_of_their_features() {
+        let mut conditions = Conditions::new();
+        conditions.enable_feature("STATIC_LIB");
+        assert_eq!(
+            enabled_sources(&conditions),
+            ["always", "either", "linux", "windows"]
+        );
+        conditions.enable_feature("bundled");
+        assert_eq!(
+            enabled_sources(&conditions),
+            ["always", "bundled", "either", "linux", "windows"]
+        );
+    }
+
+    #[test]
+    fn sources_are_enabled_for_matching_targets() {
+        let mut conditions = Conditions::new();
+        conditions.all_features().target(
+            "x86_64-unknown-linux-gnu",
+            [
+                ("unix".to_string(), None),
+                ("target_os".to_string(), Some("linux".to_string())),
+            ],
+        );
+        assert_eq!(
+            enabled_sources(&conditions),
+            ["always", "bundled", "either", "linux"]
+        );
+        conditions.target("x86_64-pc-windows-msvc", []);
+        assert_eq!(
+            enabled_sources(&conditions),
+            ["always", "bundled", "either", "windows"]
+        );
+    }
+}
@@ -0,0 +1,333 @@
+//! Locate errors in the manifest they came from and render them in the style of rustc
+//! diagnostics.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::source::SourceParseError;
+
+/// A [`SourceParseError`] together with the manifest it came from and, where it can be found, the
+/// line, column and text of the offending part of the manifest.
+///
+/// Displayed like a rustc diagnostic:
+///
+/// ```text
+/// source 'foo' has unknown key 'brnach', did you mean 'branch'?
+///  --> Cargo.toml:8:46
+///   |
+/// 8 | foo = { git = "https://example.com/foo.git", brnach = "dev" }
+///   |                                              ^^^^^^
+/// ```
+#[derive(Debug)]
+pub struct Diagnostic {
+    error: SourceParseError,
+    snippet: Snippet,
+}
+
+/// A manifest and, where known, the line, column and text of a part of it. Displayed like the
+/// location of a rustc diagnostic:
+///
+/// ```text
+///  --> Cargo.toml:8:1
+///   |
+/// 8 | foo = { tar = "https://example.com/foo.tar.gz" }
+///   | ^^^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    path: PathBuf,
+    location: Option<Location>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    /// 1-based line number
+    line: usize,
+    /// 1-based column, in characters
+    column: usize,
+    /// The text of the line
+    text: String,
+    /// The number of characters to underline
+    width: usize,
+}
+
+/// The part of a source's definition an error refers to
+enum Target<'a> {
+    /// The name of the source
+    Name,
+    /// The whole value of the source
+    Value,
+    /// A key in the source's table, or the name if the key isn't present
+    Key(&'a str),
+}
+
+impl Diagnostic {
+    /// Locate `error` in `document`, the contents of the manifest at `path`. If the document isn't
+    /// given, or the error doesn't refer to a particular part of it, only the path is reported.
+    pub fn new<P: Into<PathBuf>>(error: SourceParseError, path: P, document: Option<&str>) -> Self {
+        let location = document
+            .and_then(|document| span(&error, document).map(|span| Location::new(document, span)));
+        Self {
+            error,
+            snippet: Snippet {
+                path: path.into(),
+                location,
+            },
+        }
+    }
+
+    /// The underlying error
+    pub fn error(&self) -> &SourceParseError {
+        &self.error
+    }
+
+    /// Take the underlying error
+    pub fn into_error(self) -> SourceParseError {
+        self.error
+    }
+
+    /// The path of the manifest
+    pub fn path(&self) -> &Path {
+        self.snippet.path()
+    }
+
+    /// The 1-based line and column of the error in the manifest, if known
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        self.snippet.line_column()
+    }
+
+    /// The location of the error in the manifest
+    pub fn snippet(&self) -> &Snippet {
+        &self.snippet
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.error)?;
+        write!(f, "{}", self.snippet)
+    }
+}
+
+impl std::error::Error for Diagnostic {
+    // The error itself is part of the message, so skip straight to its cause
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl Snippet {
+    /// A manifest without a location within it
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            location: None,
+        }
+    }
+
+    /// Locate the name of `source_name` in `document`, the contents of the manifest at `path`.
+    /// Returns `None` if the manifest doesn't declare the source.
+    pub fn find<P: Into<PathBuf>>(source_name: &str, path: P, document: &str) -> Option<Self> {
+        let span = source_span(source_name, Target::Name, document)?;
+        Some(Self {
+            path: path.into(),
+            location: Some(Location::new(document, span)),
+        })
+    }
+
+    /// The path of the manifest
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The 1-based line and column in the manifest, if known
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        self.location.as_ref().map(|loc| (loc.line, loc.column))
+    }
+}
+
+impl std::fmt::Display for Snippet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(loc) = &self.location else {
+            return write!(f, " --> {}", self.path.display());
+        };
+        let gutter = " ".repeat(loc.line.to_string().len());
+        writeln!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.path.display(),
+            loc.line,
+            loc.column
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", loc.line, loc.text)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(loc.column - 1),
+            "^".repeat(loc.width.max(1))
+        )
+    }
+}
+
+impl Location {
+    fn new(document: &str, span: Range<usize>) -> Self {
+        let start = span.start.min(document.len());
+        let line_start = document[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = document[start..]
+            .find('\n')
+            .map_or(document.len(), |i| start + i);
+        let text = document[line_start..line_end].trim_end_matches('\r');
+        // Only underline the first line of a multi-line span
+        let end = span.end.clamp(start, line_start + text.len());
+        Self {
+            line: document[..start].matches('\n').count() + 1,
+            column: document[line_start..start].chars().count() + 1,
+            text: text.to_string(),
+            width: document[start..end].chars().count(),
+        }
+    }
+}
+
+/// The source, and the part of its definition, an error refers to, if any
+fn target(error: &SourceParseError) -> Option<(&str, Target<'_>)> {
+    use SourceParseError::*;
+    match error {
+        VariantUnknown { source_name }
+        | VariantMultiple { source_name }
+        | SourceConflict { source_name }
+        | VariableUndefined { source_name, .. }
+        | VariableUnterminated { source_name } => Some((source_name, Target::Name)),
+        VariantDisabled {
+            source_name,
+            variant,
+            ..
+        } => Some((source_name, Target::Key(variant))),
+        KeyUnknown {
+            source_name, key, ..
+        }
+        | KeyInvalid {
+            source_name, key, ..
+        }
+        | GitReferenceConflict {
+            source_name,
+            other: key,
+            ..
+        } => Some((source_name, Target::Key(key))),
+        ValueNotTable { name } => Some((name, Target::Value)),
+        ShorthandInvalid { source_name, .. } => Some((source_name, Target::Value)),
+        DestinationUnsafe { source_name, .. } => Some((source_name, Target::Key("dest"))),
+        DestinationOverlap { other, .. } => Some((other, Target::Key("dest"))),
+        WorkspaceTableNotFound | SourceTableNotFound | TomlInvalid(_) | JsonInvalid(_) => None,
+    }
+}
+
+/// The byte range in `document` which `error` refers to, if any
+fn span(error: &SourceParseError, document: &str) -> Option<Range<usize>> {
+    if let SourceParseError::TomlInvalid(err) = error {
+        return err.span();
+    }
+    let (name, target) = target(error)?;
+    source_span(name, target, document)
+}
+
+/// The byte range of the part of the definition of the source called `name` in `document`, if
+/// it's declared there
+fn source_span(name: &str, target: Target<'_>, document: &str) -> Option<Range<usize>> {
+    let root = toml::de::DeTable::parse(document).ok()?;
+    let (key, value) = ["package", "workspace"].iter().find_map(|table| {
+        root.get_ref()
+            .get(*table)?
+            .get_ref()
+            .get("metadata")?
+            .get_ref()
+            .get("fetch-source")?
+            .get_ref()
+            .as_table()?
+            .get_key_value(name)
+    })?;
+    Some(match target {
+        Target::Name => key.span(),
+        Target::Value => value.span(),
+        Target::Key(k) => value
+            .get_ref()
+            .as_table()
+            .and_then(|table| table.get_key_value(k))
+            .map_or(key.span(), |(k, _)| k.span()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"[package]
+name = "foo"
+
+[package.metadata.fetch-source]
+foo = { git = "https://example.com/foo.git", brnach = "dev" }
+bar = "not shorthand"
+"#;
+
+    fn diagnostic(document: &str) -> Diagnostic {
+        let err = crate::try_parse_toml(document).unwrap_err();
+        Diagnostic::new(err, "Cargo.toml", Some(document))
+    }
+
+    #[test]
+    fn unknown_key_is_located() {
+        let document = DOCUMENT.replace("bar = \"not shorthand\"\n", "");
+        let diagnostic = diagnostic(&document);
+        assert_eq!(diagnostic.line_column(), Some((5, 46)));
+        assert_eq!(
+            diagnostic.to_string(),
+            r#"source 'foo' has unknown key 'brnach', did you mean 'branch'?
+ --> Cargo.toml:5:46
+  |
+5 | foo = { git = "https://example.com/foo.git", brnach = "dev" }
+  |                                              ^^^^^^"#
+        );
+    }
+
+    #[test]
+    fn invalid_value_is_located() {
+        let document = DOCUMENT.replace("brnach", "branch");
+        let diagnostic = diagnostic(&document);
+        assert_eq!(diagnostic.line_column(), Some((6, 7)));
+        assert!(
+            diagnostic
+                .to_string()
+                .ends_with("\n  |       ^^^^^^^^^^^^^^^")
+        );
+    }
+
+    #[test]
+    fn invalid_toml_is_located() {
+        let diagnostic = diagnostic("[package]\nname = \n");
+        assert_eq!(diagnostic.line_column().map(|(line, _)| line), Some(2));
+    }
+
+    #[test]
+    fn source_is_the_cause_of_the_error() {
+        use std::error::Error;
+        let err = crate::try_parse_toml("[package]\nname = \n").unwrap_err();
+        let diagnostic = Diagnostic::new(err, "Cargo.toml", None);
+        // The parse error is part of the message, so isn't reported again as the source
+        assert!(diagnostic.source().is_none());
+    }
+
+    #[test]
+    fn sources_are_found() {
+        let snippet = Snippet::find("bar", "Cargo.toml", DOCUMENT).unwrap();
+        assert_eq!(snippet.line_column(), Some((6, 1)));
+        assert!(snippet.to_string().starts_with(" --> Cargo.toml:6:1\n"));
+        assert_eq!(Snippet::find("baz", "Cargo.toml", DOCUMENT), None);
+    }
+
+    #[test]
+    fn errors_without_a_location_report_the_path() {
+        let diagnostic = diagnostic("[package]\nname = \"foo\"\n");
+        assert_eq!(diagnostic.line_column(), None);
+        assert!(diagnostic.to_string().ends_with("\n --> Cargo.toml"));
+    }
+}
@@ -31,6 +31,10 @@ pub(crate) enum FetchErrorKind {
         status: std::process::ExitStatus,
         stderr: String,
     },
+
+    #[cfg(feature = "tar")]
+    #[error("checksum mismatch: expected sha256 {expected}, found {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
 }
 
 impl FetchErrorKind {
@@ -77,6 +81,8 @@ pub enum ErrorKind {
     SerdeDe,
     /// An error occurred while parsing sources
     Parse,
+    /// A source couldn't be fetched
+    Fetch,
 }
 
 /// Internal error categories.
@@ -97,6 +103,26 @@ pub(crate) enum ErrorImpl {
 
     #[error(transparent)]
     Parse(#[from] crate::SourceParseError),
+
+    // Boxed as a `Diagnostic` contains a line of the manifest
+    #[error(transparent)]
+    Manifest(Box<crate::Diagnostic>),
+
+    // Boxed as a `FetchError` contains its source, which would make every `Error` large
+    #[error(transparent)]
+    Fetch(Box<FetchError>),
+}
+
+impl From<crate::Diagnostic> for ErrorImpl {
+    fn from(err: crate::Diagnostic) -> Self {
+        Self::Manifest(Box::new(err))
+    }
+}
+
+impl From<FetchError> for ErrorImpl {
+    fn from(err: FetchError) -> Self {
+        Self::Fetch(Box::new(err))
+    }
 }
 
 impl ErrorImpl {
@@ -111,7 +137,8 @@ impl ErrorImpl {
             Self::Reqwest(_) => ErrorKind::Reqwest,
             Self::TomlDe(_) => ErrorKind::TomlDe,
             Self::SerdeDe(_) => ErrorKind::SerdeDe,
-            Self::Parse(_) => ErrorKind::Parse,
+            Self::Parse(_) | Self::Manifest(_) => ErrorKind::Parse,
+            Self::Fetch(_) => ErrorKind::Fetch,
         };
         Error { kind, inner }
     }
@@ -5,6 +5,7 @@ use std::io::Read;
 use crate::error::FetchErrorKind;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 /// A reference to a specific branch, tag, or commit in a git repository.
 pub enum GitReference {
     #[serde(rename = "branch")]
@@ -20,11 +21,15 @@ pub enum GitReference {
 
 /// Represents a remote git repository to be cloned.
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Git {
+    /// The SSH or HTTPS upstream URL
     #[serde(rename = "git")]
     url: String,
+    /// What to clone. Defaults to the default branch.
     #[serde(flatten)]
     reference: Option<GitReference>,
+    /// Whether to recursively clone submodules
     #[serde(default)]
     recursive: bool,
 }
@@ -57,6 +62,11 @@ impl Git {
         }
     }
 
+    /// The selected branch, tag or commit, if any.
+    pub fn reference(&self) -> Option<&GitReference> {
+        self.reference.as_ref()
+    }
+
     /// The selected commit SHA, if any.
     pub fn commit_sha(&self) -> Option<&str> {
         match self.reference.as_ref() {
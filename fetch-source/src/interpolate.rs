@@ -0,0 +1,261 @@
+//! Expand variables in the values of a source's table.
+//!
+//! - `${version}` is the value of the source's `version` key.
+//! - `${target}` is the target triple given by [`Variables`], which is the `TARGET` environment
+//!   variable cargo sets for build scripts unless given explicitly.
+//! - Any other `${NAME}` is the value of the environment variable `NAME`.
+//!
+//! Write `$${` for a literal `${`.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use crate::source::SourceParseError;
+
+/// The key giving the value of `${version}`
+const VERSION_KEY: &str = "version";
+
+/// The values of the variables which source definitions may use, other than `${version}` and
+/// environment variables. Also records which environment variables were read.
+#[derive(Debug, Default, Clone)]
+pub struct Variables {
+    /// The value of `${target}`
+    target: Option<String>,
+    /// The environment variables read while expanding `${NAME}`
+    env_read: RefCell<BTreeSet<String>>,
+}
+
+impl PartialEq for Variables {
+    fn eq(&self, other: &Self) -> bool {
+        self.target == other.target
+    }
+}
+
+impl Eq for Variables {}
+
+impl Variables {
+    /// No variables, so `${target}` is undefined
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `${target}` from the `TARGET` environment variable cargo sets for build scripts.
+    pub fn from_env() -> Self {
+        Self {
+            target: std::env::var("TARGET").ok(),
+            ..Self::default()
+        }
+    }
+
+    /// Set the target triple which `${target}` expands to.
+    pub fn target<S: Into<String>>(&mut self, triple: S) -> &mut Self {
+        self.target = Some(triple.into());
+        self
+    }
+
+    /// The names of the environment variables which `${NAME}` has read so far, whether or not
+    /// they were defined, e.g. so that build scripts can be rerun when they change.
+    pub fn env_vars_read(&self) -> Vec<String> {
+        self.env_read.borrow().iter().cloned().collect()
+    }
+}
+
+/// Expand the variables in every string in a source's table. The `version` key may itself refer
+/// to environment variables, but not to `${version}`.
+pub(crate) fn interpolate(
+    source_name: &str,
+    table: &toml::Table,
+    variables: &Variables,
+) -> Result<toml::Table, SourceParseError> {
+    let context = Context {
+        source_name,
+        variables,
+        version: None,
+    };
+    let version = match table.get(VERSION_KEY).and_then(|v| v.as_str()) {
+        Some(version) => Some(context.expand(version)?),
+        None => None,
+    };
+    let context = Context {
+        version: version.as_deref(),
+        ..context
+    };
+    table
+        .iter()
+        .map(|(key, value)| Ok((key.clone(), context.expand_value(value)?)))
+        .collect()
+}
+
+/// What variables expand to within a source's table
+#[derive(Clone, Copy)]
+struct Context<'a> {
+    source_name: &'a str,
+    variables: &'a Variables,
+    version: Option<&'a str>,
+}
+
+impl Context<'_> {
+    fn expand_value(&self, value: &toml::Value) -> Result<toml::Value, SourceParseError> {
+        Ok(match value {
+            toml::Value::String(s) => toml::Value::String(self.expand(s)?),
+            toml::Value::Array(values) => toml::Value::Array(
+                values
+                    .iter()
+                    .map(|v| self.expand_value(v))
+                    .collect::<Result<_, _>>()?,
+            ),
+            toml::Value::Table(table) => toml::Value::Table(
+                table
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), self.expand_value(v)?)))
+                    .collect::<Result<_, SourceParseError>>()?,
+            ),
+            other => other.clone(),
+        })
+    }
+
+    /// Expand the variables in a single string
+    fn expand(&self, value: &str) -> Result<String, SourceParseError> {
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(start) = rest.find('$') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("$${") {
+                expanded.push_str("${");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("${") {
+                let end =
+                    after
+                        .find('}')
+                        .ok_or_else(|| SourceParseError::VariableUnterminated {
+                            source_name: self.source_name.to_string(),
+                        })?;
+                expanded.push_str(&self.lookup(&after[..end])?);
+                rest = &after[end + 1..];
+            } else {
+                expanded.push('$');
+                rest = &rest[1..];
+            }
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    fn lookup(&self, variable: &str) -> Result<String, SourceParseError> {
+        let value = match variable {
+            VERSION_KEY => self.version.map(str::to_string),
+            "target" => self.variables.target.clone(),
+            name => {
+                self.variables
+                    .env_read
+                    .borrow_mut()
+                    .insert(name.to_string());
+                std::env::var(name).ok()
+            }
+        };
+        value.ok_or_else(|| SourceParseError::VariableUndefined {
+            source_name: self.source_name.to_string(),
+            variable: variable.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Source, try_parse_toml, try_parse_toml_with_options, try_parse_toml_with_variables,
+    };
+
+    #[test]
+    fn version_is_expanded_before_digesting() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            templated = { git = "https://example.com/foo.git", tag = "v${version}", version = "1.2" }
+            literal = { git = "https://example.com/foo.git", tag = "v1.2" }
+        "#;
+        let (sources, options) = try_parse_toml_with_options(document).unwrap();
+        assert_eq!(sources["templated"], sources["literal"]);
+        assert_eq!(
+            Source::digest(&sources["templated"]),
+            Source::digest(&sources["literal"])
+        );
+        assert_eq!(options["templated"].version(), Some("1.2"));
+    }
+
+    #[test]
+    fn environment_variables_are_expanded() {
+        // Cargo sets this when running tests
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "https://example.com/${CARGO_PKG_NAME}.git", tag = "$${literal}" }
+        "#;
+        let sources = try_parse_toml(document).unwrap();
+        let expected = Source::git(
+            "https://example.com/fetch-source.git",
+            Some(crate::GitReference::Tag("${literal}".into())),
+            false,
+        );
+        assert_eq!(sources["foo"], expected);
+    }
+
+    #[test]
+    fn target_is_given_explicitly() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "https://example.com/foo.git", tag = "${target}" }
+        "#;
+        let mut variables = Variables::new();
+        assert!(matches!(
+            try_parse_toml_with_variables(document, &variables),
+            Err(SourceParseError::VariableUndefined { variable, .. }) if variable == "target"
+        ));
+        variables.target("x86_64-unknown-linux-gnu");
+        let (sources, _) = try_parse_toml_with_variables(document, &variables).unwrap();
+        let expected = Source::git(
+            "https://example.com/foo.git",
+            Some(crate::GitReference::Tag("x86_64-unknown-linux-gnu".into())),
+            false,
+        );
+        assert_eq!(sources["foo"], expected);
+    }
+
+    #[test]
+    fn undefined_variables_fail() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "https://example.com/foo.git", tag = "v${version}" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(SourceParseError::VariableUndefined { source_name, variable })
+                if source_name == "foo" && variable == "version"
+        ));
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "https://example.com/foo.git", tag = "v${version" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(SourceParseError::VariableUnterminated { .. })
+        ));
+    }
+
+    #[test]
+    fn environment_variables_read_are_recorded() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "https://example.com/${CARGO_PKG_NAME}.git", tag = "${target}" }
+            undefined = { git = "https://example.com/bar.git", tag = "${FETCH_SOURCE_UNDEFINED}" }
+        "#;
+        let mut variables = Variables::new();
+        variables.target("x86_64-unknown-linux-gnu");
+        assert!(try_parse_toml_with_variables(document, &variables).is_err());
+        // `${target}` isn't an environment variable, but undefined variables are recorded
+        assert_eq!(
+            variables.env_vars_read(),
+            ["CARGO_PKG_NAME", "FETCH_SOURCE_UNDEFINED"]
+        );
+    }
+}
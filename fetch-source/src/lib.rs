@@ -24,6 +24,13 @@
 //! - `tar`: Download and extract `.tar.gz` archives. This is an optional feature because it uses the
 //!   [`reqwest`] crate which brings quite a few more dependencies.
 //! - `rayon`: Fetch sources in parallel with [`rayon`].
+//! - `bundle`: Export cached artefacts to, and import them from, a single archive with
+//!   `export_bundle` and `import_bundle`. Useful for moving caches to machines without network
+//!   access.
+//! - `pack`: Pack artefacts which haven't been used recently into compressed archives to save space
+//!   with `Cache::pack_unused`. Packed artefacts are unpacked on demand by [`Cache::access`].
+//! - `schema`: Generate a JSON Schema of the `fetch-source` table with `table_schema`, or of a
+//!   manifest containing it with `manifest_schema`, for editors to validate source definitions.
 //!
 //! [`reqwest`]: https://crates.io/crates/reqwest
 //! [`rayon`]: https://crates.io/crates/rayon
@@ -106,7 +113,7 @@ fetch_source::try_parse_toml(cargo_toml)?.into_par_iter()
 //! ```rust
 //! # use fetch_source::Cache;
 //! # fn main() -> Result<(), fetch_source::Error> {
-//! let cache = Cache::load_or_create(std::env::temp_dir())?;
+//! let mut cache = Cache::load_or_create(std::env::temp_dir())?;
 //!
 //! let project1 = r#"
 //! [package.metadata.fetch-source]
@@ -114,8 +121,8 @@ fetch_source::try_parse_toml(cargo_toml)?.into_par_iter()
 //! "#;
 //!
 //! let sources1 = fetch_source::try_parse_toml(project1)?;
-//! // Check where this source would be cached
-//! let cache_latest = cache.cached_path(&sources1.get("syn::latest").unwrap());
+//! // Check where this source is, or would be, cached
+//! let cache_latest = cache.artefact_path(&sources1.get("syn::latest").unwrap());
 //!
 //! // Note the re-use of 'syn::latest' with a different definition!
 //! let project2 = r#"
@@ -125,8 +132,8 @@ fetch_source::try_parse_toml(cargo_toml)?.into_par_iter()
 //! "#;
 //!
 //! let sources2 = fetch_source::try_parse_toml(project2)?;
-//! let cache_greatest = cache.cached_path(&sources2.get("syn::greatest").unwrap());
-//! let cache_dev = cache.cached_path(&sources2.get("syn::latest").unwrap());
+//! let cache_greatest = cache.artefact_path(&sources2.get("syn::greatest").unwrap());
+//! let cache_dev = cache.artefact_path(&sources2.get("syn::latest").unwrap());
 //!
 //! // The same source by a different name from a different project is the same in the cache
 //! assert_eq!(cache_latest, cache_greatest);
@@ -134,21 +141,71 @@ fetch_source::try_parse_toml(cargo_toml)?.into_par_iter()
 //! // The name doesn't uniquely identify a source - only the definition of the source matters
 //! assert_ne!(cache_latest, cache_dev);
 //!
+//! // Get the path of a cached artefact to use it, unpacking it first if it was packed
+//! if let Some(path) = cache.access(&sources1["syn::latest"])? {
+//!     println!("syn is cached at {}", path.display());
+//! }
+//!
 //! # Ok(())
 //! # }
 //! ```
 //!
+//! A cache can be layered over read-only caches, such as a pre-populated cache shared between
+//! users, with [`Cache::add_read_only`]. Sources found in a read-only cache are never fetched
+//! again, and newly-fetched sources are always stored in the writable cache.
+//!
+//! Sources with different definitions often have identical files, e.g. the same tag fetched as a
+//! tar archive and as a git repo. Use [`Cache::deduplicate`] to store identical files in a cache
+//! only once.
+//!
+//! Consumers of cached artefacts should get their path with [`Cache::access`] rather than
+//! [`Cache::artefact_path`]. This records when each artefact was last used and, with the `pack`
+//! feature, transparently unpacks artefacts which were packed because they hadn't been used
+//! recently.
+//!
+//! # Build scripts
+//!
+//! The [`build`] module fetches the sources declared in a crate's manifest from its build script,
+//! telling cargo when to re-run the build script and returning the path of each source.
+//!
+//! # Workspaces
+//!
+//! In a cargo workspace, sources may also be declared in the `workspace.metadata.fetch-source`
+//! table of the root manifest. Use [`Workspace::find`] and [`Workspace::load_sources`] to get the
+//! union of the sources declared in the root and by every member.
+//!
+//! To find packages and their sources exactly as cargo sees them, use [`CargoMetadata`] to run (or
+//! parse the output of) `cargo metadata`.
+//!
+//! # Checking sources
+//!
+//! Use [`Lints::check`] to find sources which are likely to cause problems even though they parse,
+//! such as archives without a checksum or git sources which follow a branch. Each [`Lint`] can be
+//! allowed, reported as a warning, or denied.
+//!
+//! # Vendoring sources
+//!
+//! Sources can be copied into a project-local directory (by default [`DEFAULT_VENDOR_DIR`]) so
+//! that a project can be built without fetching anything, e.g. for source releases. A [`Vendor`]
+//! records which sources were vendored; use [`load_vendor`] and [`Vendor::partition`] to prefer
+//! vendored copies over fetching.
+//!
 //! # Declaring sources
 //!
 //! The keys in the `package.metadata.fetch-source` table name a remote source. They can include
 //! any path character and zero or more '`::`' separators. Each `::`-separated component of a
-//! name maps to a subdirectory of the output directory.
+//! name maps to a subdirectory of the output directory. Names (and destinations given with `dest`)
+//! must not contain `..` or absolute components, and no two sources may be copied to the same
+//! directory or one inside the other.
 //!
-//! Each value in the `package.metadata.fetch-source` table must be a table which identifies the
-//! remote source it represents:
+//! Each value in the `package.metadata.fetch-source` table must be a table (or a shorthand string,
+//! see below) which identifies the remote source it represents. Unknown keys, and keys which
+//! belong to a different type of source, are rejected:
 //!
 //! **Tar archives**
 //! - The `tar` key gives the URL of the archive.
+//! - The optional `sha256` key gives the expected SHA-256 digest of the archive. Fetching fails if
+//!   the downloaded archive doesn't match.
 //!
 //! **Git repos**
 //! - The `git` key gives the SSH or HTTPS upstream URL.
@@ -157,34 +214,103 @@ fetch_source::try_parse_toml(cargo_toml)?.into_par_iter()
 //! - Use `recursive = true` to recursively clone submodules.
 //! - All clones are shallow, i.e. with a depth of 1.
 //!
+//! **Shorthand**
+//! - A string value is shorthand for a source: either a git URL prefixed with `git+`, optionally
+//!   followed by a `?branch=`, `?tag=` or `?rev=` query and a `#recursive` fragment, e.g.
+//!   `"git+https://github.com/user/repo.git?tag=v1#recursive"`, or the URL of a `.tar.gz` or `.tgz`
+//!   archive. Use a table to give any options.
+//!
+//! **Options**
+//! - The `dest` key gives the path the source is copied to, relative to the output directory,
+//!   instead of the path derived from its name. Use [`try_parse_toml_with_options`] to get the
+//!   [`SourceOptions`] of each source.
+//! - The `features` key lists cargo features, any one of which enables the source, e.g.
+//!   `features = ["bundled"]`. Sources without `features` are always enabled. Use [`Conditions`]
+//!   to remove the sources which aren't enabled.
+//! - The `version` key gives the value of `${version}` in the source's other values, e.g.
+//!   `tar = "https://example.com/foo-${version}.tar.gz"`. Values may also use `${target}` for the
+//!   target triple and `${NAME}` for any other environment variable; write `$${` for a literal
+//!   `${`. Sources are identified by their expanded definition. The target triple is read from the
+//!   `TARGET` environment variable unless given with [`Variables`], e.g. in
+//!   [`try_parse_toml_with_variables`].
+//! - The `target` key gives the platforms the source is needed for, as a target triple, a
+//!   `cfg(...)` expression like those in `[target.'cfg(...)'.dependencies]`, or a list of either,
+//!   e.g. `target = 'cfg(target_os = "linux")'`. Sources without `target` are needed everywhere.
+//!
 
+pub mod build;
+#[cfg(feature = "bundle")]
+mod bundle;
 mod cache;
+mod cfg;
+mod conditions;
+mod diagnostic;
 mod error;
+mod fs;
 mod git;
+mod interpolate;
+mod lint;
+mod metadata;
+mod objects;
+#[cfg(feature = "pack")]
+mod pack;
+#[cfg(feature = "schema")]
+mod schema;
 mod source;
 #[cfg(feature = "tar")]
 mod tar;
+mod vendor;
+mod workspace;
 
 /// The build-time git commit hash
 pub static GIT_SHA: &str = env!("VERGEN_GIT_SHA");
 
+#[cfg(feature = "bundle")]
+pub use bundle::{export_bundle, import_bundle};
 pub use cache::{Cache, CacheDir, CacheItems, CacheRoot, RelCacheDir};
+pub use cfg::Platform;
+pub use conditions::Conditions;
+pub use diagnostic::{Diagnostic, Snippet};
 pub use error::{Error, ErrorKind, FetchError};
 pub use git::{Git, GitReference};
+pub use interpolate::Variables;
+pub use lint::{Finding, Lint, Lints, Severity};
+pub use metadata::{CargoMetadata, CargoPackage};
+pub use objects::DedupStats;
+#[cfg(feature = "schema")]
+pub use schema::{manifest_schema, table_schema};
 pub use source::{
-    Artefact, Digest, FetchResult, Source, SourceName, SourceParseError, SourcesTable,
-    try_parse_toml,
+    Artefact, Digest, FetchResult, Source, SourceName, SourceOptions, SourceOptionsTable,
+    SourceParseError, SourcesTable, try_parse_toml, try_parse_toml_with_options,
+    try_parse_toml_with_variables,
 };
 #[cfg(feature = "tar")]
 pub use tar::Tar;
+pub use vendor::{DEFAULT_VENDOR_DIR, Vendor};
+pub use workspace::{
+    Workspace, try_parse_workspace_toml, try_parse_workspace_toml_with_variables, union_sources,
+};
 
 /// Convenience function to load sources from `Cargo.toml` in the given directory
 ///
 /// Returns an error if the manifest can't be loaded or if deserialisation fails.
 pub fn load_sources<P: AsRef<std::path::Path>>(path: P) -> Result<SourcesTable, Error> {
-    Ok(try_parse_toml(&std::fs::read_to_string(
-        path.as_ref().to_path_buf().join("Cargo.toml"),
-    )?)?)
+    let manifest = path.as_ref().join("Cargo.toml");
+    let document = std::fs::read_to_string(&manifest)?;
+    try_parse_toml(&document).map_err(|err| Diagnostic::new(err, manifest, Some(&document)).into())
+}
+
+/// Convenience function to load the sources vendored for the project in the given directory, if
+/// any. Sources are expected to be vendored in [`DEFAULT_VENDOR_DIR`] next to `Cargo.toml`.
+///
+/// Returns an error if the vendor file exists but can't be read.
+pub fn load_vendor<P: AsRef<std::path::Path>>(path: P) -> Result<Option<Vendor>, Error> {
+    let vendor_dir = path.as_ref().join(DEFAULT_VENDOR_DIR);
+    if Vendor::vendor_file_exists(&vendor_dir) {
+        Ok(Some(Vendor::read(vendor_dir)?))
+    } else {
+        Ok(None)
+    }
 }
 
 /// Convenience function to fetch all sources serially
@@ -225,6 +351,8 @@ mod par {
     }
 
     /// Convenience function to update the given cache with all missing sources in parallel.
+    /// Sources found in any of the cache's read-only caches are not fetched again, and sources
+    /// declared more than once under different names are only fetched once.
     /// Returns any errors that occurred when fetching the missing sources.
     pub fn cache_all_par(
         cache: &mut Cache,
@@ -232,9 +360,11 @@ mod par {
     ) -> Vec<(SourceName, FetchError)> {
         let items = cache.items();
         let cache_root = cache.cache_dir();
+        let mut seen = std::collections::BTreeSet::new();
         let results = sources
             .into_iter()
-            .filter(|(_, source)| !items.contains(source))
+            .filter(|(_, source)| !cache.contains(source))
+            .filter(|(_, source)| seen.insert(Source::digest(source)))
             .collect::<Vec<_>>()
             .into_par_iter()
             .map(|(name, source)| {
@@ -0,0 +1,363 @@
+//! Check declared sources for definitions which parse but are likely to cause problems, such as
+//! insecure URLs or references which may change between fetches.
+
+use std::collections::BTreeMap;
+
+use crate::{GitReference, Source, SourcesTable, Vendor};
+
+/// A check applied to each declared source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    /// The source is fetched over an unencrypted `http://` or `git://` URL
+    InsecureUrl,
+    /// A git source follows a branch (or the default branch) rather than a `tag` or `rev`, and
+    /// isn't pinned by a vendored copy, so what is fetched may change over time
+    FloatingReference,
+    /// A tar archive has no `sha256` checksum, so its contents aren't verified
+    MissingChecksum,
+    /// The source has the same definition as another source declared under a different name
+    DuplicateSource,
+}
+
+impl Lint {
+    /// Every lint, in the order they are checked
+    pub const ALL: &[Lint] = &[
+        Lint::InsecureUrl,
+        Lint::FloatingReference,
+        Lint::MissingChecksum,
+        Lint::DuplicateSource,
+    ];
+
+    /// The name of the lint, as accepted by [`Lint::from_str`](std::str::FromStr::from_str)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::InsecureUrl => "insecure-url",
+            Lint::FloatingReference => "floating-reference",
+            Lint::MissingChecksum => "missing-checksum",
+            Lint::DuplicateSource => "duplicate-source",
+        }
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Lint {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .iter()
+            .find(|lint| lint.name() == name)
+            .copied()
+            .ok_or_else(|| {
+                let names = Lint::ALL.iter().map(Lint::name).collect::<Vec<_>>();
+                format!(
+                    "unknown lint '{name}', expected one of: {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How a lint is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Severity {
+    /// The lint isn't checked
+    Allow,
+    /// Findings are reported as warnings
+    #[default]
+    Warn,
+    /// Findings are reported as errors
+    Deny,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Allow => write!(f, "allow"),
+            Severity::Warn => write!(f, "warning"),
+            Severity::Deny => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found by a lint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    lint: Lint,
+    severity: Severity,
+    source_name: String,
+    message: String,
+}
+
+impl Finding {
+    /// The lint which found the problem
+    pub fn lint(&self) -> Lint {
+        self.lint
+    }
+
+    /// How the problem should be reported
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The name of the source with the problem
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+
+    /// A description of the problem
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.lint, self.message)
+    }
+}
+
+/// The severity of each lint. Every lint is a warning unless configured otherwise.
+///
+/// ```rust
+/// use fetch_source::{Lint, Lints, Severity};
+///
+/// let sources = fetch_source::try_parse_toml(r#"
+/// [package.metadata.fetch-source]
+/// foo = { git = "http://example.com/foo.git", rev = "0123abc" }
+/// "#)?;
+///
+/// let mut lints = Lints::default();
+/// lints.set(Lint::InsecureUrl, Severity::Deny);
+/// let findings = lints.check(&sources);
+/// assert_eq!(findings.len(), 1);
+/// assert_eq!(findings[0].severity(), Severity::Deny);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    severities: BTreeMap<Lint, Severity>,
+}
+
+impl Lints {
+    /// Set the severity of a lint
+    pub fn set(&mut self, lint: Lint, severity: Severity) -> &mut Self {
+        self.severities.insert(lint, severity);
+        self
+    }
+
+    /// Report every lint which is currently a warning as an error instead
+    pub fn deny_warnings(&mut self) -> &mut Self {
+        for lint in Lint::ALL {
+            if self.severity(*lint) == Severity::Warn {
+                self.set(*lint, Severity::Deny);
+            }
+        }
+        self
+    }
+
+    /// The severity of a lint
+    pub fn severity(&self, lint: Lint) -> Severity {
+        self.severities.get(&lint).copied().unwrap_or_default()
+    }
+
+    /// Check every source, returning the problems found by lints which aren't allowed, in order of
+    /// source name
+    pub fn check(&self, sources: &SourcesTable) -> Vec<Finding> {
+        self.check_with_vendor(sources, None)
+    }
+
+    /// As [`Lints::check`], but sources vendored with the same definition in `vendor` are pinned
+    /// to their vendored copy, so aren't reported as floating references.
+    pub fn check_with_vendor(
+        &self,
+        sources: &SourcesTable,
+        vendor: Option<&Vendor>,
+    ) -> Vec<Finding> {
+        let mut first_names = BTreeMap::new();
+        let mut findings = Vec::new();
+        // Visit sources in a stable order so the same duplicate is always reported
+        let sources = sources.iter().collect::<BTreeMap<_, _>>();
+        for (name, source) in sources {
+            let mut report = |lint: Lint, message: String| {
+                let severity = self.severity(lint);
+                if severity != Severity::Allow {
+                    findings.push(Finding {
+                        lint,
+                        severity,
+                        source_name: name.clone(),
+                        message,
+                    });
+                }
+            };
+            let url = upstream(source);
+            if is_insecure(url) {
+                report(
+                    Lint::InsecureUrl,
+                    format!("source '{name}' is fetched over an insecure URL '{url}'"),
+                );
+            }
+            match source {
+                #[cfg(feature = "tar")]
+                Source::Tar(tar) => {
+                    if tar.sha256().is_none() {
+                        report(
+                            Lint::MissingChecksum,
+                            format!("source '{name}' is an archive without a 'sha256' checksum"),
+                        );
+                    }
+                }
+                Source::Git(_) if vendor.is_some_and(|v| v.get(name, source).is_some()) => {}
+                Source::Git(git) => match git.reference() {
+                    None => report(
+                        Lint::FloatingReference,
+                        format!("source '{name}' follows the default branch; pin it with 'rev'"),
+                    ),
+                    Some(GitReference::Branch(branch)) => report(
+                        Lint::FloatingReference,
+                        format!("source '{name}' follows branch '{branch}'; pin it with 'rev'"),
+                    ),
+                    Some(GitReference::Tag(_) | GitReference::Rev(_)) => {}
+                },
+            }
+            match first_names.entry(Source::digest(source)) {
+                std::collections::btree_map::Entry::Occupied(first) => report(
+                    Lint::DuplicateSource,
+                    format!(
+                        "source '{name}' has the same definition as '{}'",
+                        first.get()
+                    ),
+                ),
+                std::collections::btree_map::Entry::Vacant(entry) => {
+                    entry.insert(name.clone());
+                }
+            }
+        }
+        findings
+    }
+}
+
+fn upstream(source: &Source) -> &str {
+    match source {
+        #[cfg(feature = "tar")]
+        Source::Tar(tar) => tar.upstream(),
+        Source::Git(git) => git.upstream(),
+    }
+}
+
+/// Whether a URL uses a scheme which isn't encrypted
+fn is_insecure(url: &str) -> bool {
+    ["http://", "git://"].iter().any(|scheme| {
+        url.get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(findings: &[Finding]) -> Vec<(&str, Lint)> {
+        findings
+            .iter()
+            .map(|finding| (finding.source_name(), finding.lint()))
+            .collect()
+    }
+
+    #[test]
+    fn git_sources_are_checked() {
+        let sources = crate::try_parse_toml(
+            r#"
+            [package.metadata.fetch-source]
+            insecure = { git = "git://example.com/foo.git", rev = "0123abc" }
+            branch = { git = "https://example.com/foo.git", branch = "dev" }
+            default = { git = "https://example.com/bar.git" }
+            tag = { git = "https://example.com/foo.git", tag = "v1" }
+            same-tag = { git = "https://example.com/foo.git", tag = "v1" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            lints(&Lints::default().check(&sources)),
+            [
+                ("branch", Lint::FloatingReference),
+                ("default", Lint::FloatingReference),
+                ("insecure", Lint::InsecureUrl),
+                ("tag", Lint::DuplicateSource),
+            ]
+        );
+    }
+
+    #[test]
+    fn vendored_sources_are_pinned() {
+        let sources = crate::try_parse_toml(
+            r#"
+            [package.metadata.fetch-source]
+            vendored = { git = "https://example.com/foo.git", branch = "dev" }
+            changed = { git = "https://example.com/bar.git", branch = "dev" }
+            "#,
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut vendor = Vendor::load_or_create(dir.path()).unwrap();
+        vendor.insert("vendored".into(), sources["vendored"].clone());
+        // Vendored from a different definition, so no longer pinned
+        vendor.insert(
+            "changed".into(),
+            Source::git("https://example.com/bar.git", None, false),
+        );
+        assert_eq!(
+            lints(&Lints::default().check_with_vendor(&sources, Some(&vendor))),
+            [("changed", Lint::FloatingReference)]
+        );
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn archives_are_checked() {
+        let sources = crate::try_parse_toml(
+            r#"
+            [package.metadata.fetch-source]
+            insecure = { tar = "HTTP://example.com/foo.tar.gz", sha256 = "abc" }
+            unverified = { tar = "https://example.com/foo.tar.gz" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            lints(&Lints::default().check(&sources)),
+            [
+                ("insecure", Lint::InsecureUrl),
+                ("unverified", Lint::MissingChecksum)
+            ]
+        );
+    }
+
+    #[test]
+    fn severities_are_configurable() {
+        let sources = crate::try_parse_toml(
+            r#"
+            [package.metadata.fetch-source]
+            foo = { git = "http://example.com/foo.git" }
+            "#,
+        )
+        .unwrap();
+        let mut lints = Lints::default();
+        lints
+            .set(Lint::FloatingReference, Severity::Allow)
+            .deny_warnings();
+        let findings = lints.check(&sources);
+        assert_eq!(
+            findings.iter().map(Finding::to_string).collect::<Vec<_>>(),
+            [
+                "error[insecure-url]: source 'foo' is fetched over an insecure URL 'http://example.com/foo.git'"
+            ]
+        );
+        assert!("floating-reference".parse::<Lint>().is_ok());
+        assert!("floating".parse::<Lint>().is_err());
+    }
+}
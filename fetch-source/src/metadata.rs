@@ -0,0 +1,219 @@
+//! Discover the sources declared by packages as cargo sees them, using the output of
+//! `cargo metadata`.
+
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+use crate::interpolate::Variables;
+use crate::source::{SourceOptionsTable, SourceParseError, SourcesTable, try_parse_with_options};
+
+/// A workspace member as reported by `cargo metadata`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CargoPackage {
+    name: String,
+    manifest_path: PathBuf,
+    sources_table: Option<toml::Table>,
+}
+
+impl CargoPackage {
+    /// The name of the package
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The path of the package's manifest
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// Parse the sources in the package's `package.metadata.fetch-source` table, if it has one,
+    /// expanding the given [`Variables`].
+    pub fn sources(
+        &self,
+        variables: &Variables,
+    ) -> Result<Option<(SourcesTable, SourceOptionsTable)>, SourceParseError> {
+        self.sources_table
+            .as_ref()
+            .map(|table| try_parse_with_options(table, variables))
+            .transpose()
+    }
+}
+
+/// The workspace members and workspace metadata reported by `cargo metadata`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CargoMetadata {
+    workspace_root: PathBuf,
+    workspace_sources_table: Option<toml::Table>,
+    packages: Vec<CargoPackage>,
+}
+
+/// The parts of the `cargo metadata` output we care about
+#[derive(serde::Deserialize)]
+struct RawMetadata {
+    packages: Vec<RawPackage>,
+    workspace_members: Vec<String>,
+    workspace_root: PathBuf,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+#[derive(serde::Deserialize)]
+struct RawPackage {
+    name: String,
+    id: String,
+    manifest_path: PathBuf,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+/// Get the `fetch-source` table from a `metadata` value, if any
+fn sources_table(
+    metadata: Option<serde_json::Value>,
+) -> Result<Option<toml::Table>, SourceParseError> {
+    match metadata.and_then(|mut v| v.get_mut("fetch-source").map(serde_json::Value::take)) {
+        Some(table) => Ok(Some(serde_json::from_value(table)?)),
+        None => Ok(None),
+    }
+}
+
+impl CargoMetadata {
+    /// Parse the JSON output of `cargo metadata --format-version 1`. Only workspace members are
+    /// kept.
+    pub fn parse<S: AsRef<str>>(json: S) -> Result<Self, SourceParseError> {
+        let raw: RawMetadata = serde_json::from_str(json.as_ref())?;
+        let mut packages = Vec::new();
+        for package in raw.packages {
+            if raw.workspace_members.contains(&package.id) {
+                packages.push(CargoPackage {
+                    name: package.name,
+                    manifest_path: package.manifest_path,
+                    sources_table: sources_table(package.metadata)?,
+                });
+            }
+        }
+        Ok(Self {
+            workspace_root: raw.workspace_root,
+            workspace_sources_table: sources_table(raw.metadata)?,
+            packages,
+        })
+    }
+
+    /// Run `cargo metadata` for the given manifest and parse its output. Uses the `cargo` given by
+    /// the `CARGO` environment variable if set, e.g. in build scripts, otherwise `cargo` on `PATH`.
+    pub fn load<P: AsRef<Path>>(manifest_path: P) -> Result<Self, Error> {
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let output = std::process::Command::new(cargo)
+            .args([
+                "metadata",
+                "--format-version",
+                "1",
+                "--no-deps",
+                "--manifest-path",
+            ])
+            .arg(manifest_path.as_ref())
+            .output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "cargo metadata exited with status {}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+            .into());
+        }
+        Ok(Self::parse(String::from_utf8_lossy(&output.stdout))?)
+    }
+
+    /// The workspace root directory
+    pub fn workspace_root(&self) -> &Path {
+        &self.workspace_root
+    }
+
+    /// The workspace members
+    pub fn packages(&self) -> &[CargoPackage] {
+        &self.packages
+    }
+
+    /// The workspace member with the given name, if any
+    pub fn package<S: AsRef<str>>(&self, name: S) -> Option<&CargoPackage> {
+        self.packages.iter().find(|p| p.name == name.as_ref())
+    }
+
+    /// Parse the sources in the workspace's `workspace.metadata.fetch-source` table, if it has
+    /// one, expanding the given [`Variables`].
+    pub fn workspace_sources(
+        &self,
+        variables: &Variables,
+    ) -> Result<Option<(SourcesTable, SourceOptionsTable)>, SourceParseError> {
+        self.workspace_sources_table
+            .as_ref()
+            .map(|table| try_parse_with_options(table, variables))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{
+        "packages": [
+            {
+                "name": "with-sources",
+                "id": "path+file:///ws/a#0.1.0",
+                "manifest_path": "/ws/a/Cargo.toml",
+                "metadata": {
+                    "fetch-source": {
+                        "foo": { "git": "git@github.com:foo/foo.git" }
+                    }
+                }
+            },
+            {
+                "name": "without-sources",
+                "id": "path+file:///ws/b#0.1.0",
+                "manifest_path": "/ws/b/Cargo.toml",
+                "metadata": null
+            },
+            {
+                "name": "not-a-member",
+                "id": "registry+https://example.com#dep@1.0.0",
+                "manifest_path": "/registry/dep/Cargo.toml",
+                "metadata": null
+            }
+        ],
+        "workspace_members": ["path+file:///ws/a#0.1.0", "path+file:///ws/b#0.1.0"],
+        "workspace_root": "/ws",
+        "metadata": {
+            "fetch-source": {
+                "bar": { "git": "git@github.com:foo/bar.git", "branch": "dev" }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn parse_members_and_workspace_sources() {
+        let metadata = CargoMetadata::parse(METADATA).unwrap();
+        let variables = Variables::new();
+        assert_eq!(metadata.workspace_root(), Path::new("/ws"));
+        assert_eq!(metadata.packages().len(), 2);
+        assert!(metadata.package("not-a-member").is_none());
+
+        let (sources, _) = metadata
+            .package("with-sources")
+            .unwrap()
+            .sources(&variables)
+            .unwrap()
+            .unwrap();
+        assert!(sources.contains_key("foo"));
+        assert!(
+            metadata
+                .package("without-sources")
+                .unwrap()
+                .sources(&variables)
+                .unwrap()
+                .is_none()
+        );
+
+        let (sources, _) = metadata.workspace_sources(&variables).unwrap().unwrap();
+        assert!(sources.contains_key("bar"));
+    }
+}
@@ -0,0 +1,177 @@
+//! A content-addressed store of files within a cache, used to store files which are identical
+//! between (or within) artefacts only once.
+//!
+//! Each object is named by the SHA-256 digest of its contents, and files in artefact directories
+//! are replaced with hard links to their object. Objects which are no longer linked from any
+//! artefact can be removed with [`prune`].
+
+use std::path::{Path, PathBuf};
+
+/// The name of the object store directory within the cache root.
+pub(crate) const OBJECTS_DIR: &str = "objects";
+
+/// Statistics about the files deduplicated by [`Cache::deduplicate`](crate::Cache::deduplicate).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DedupStats {
+    /// The number of files examined
+    pub files: usize,
+    /// The number of files replaced with a link to an identical file
+    pub linked: usize,
+    /// The number of bytes no longer stored more than once
+    pub bytes_saved: u64,
+}
+
+impl std::ops::AddAssign for DedupStats {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.linked += other.linked;
+        self.bytes_saved += other.bytes_saved;
+    }
+}
+
+/// The path of the object with the given digest. Objects are spread across subdirectories by the
+/// first two characters of their digest to keep directories small.
+fn object_path(objects_dir: &Path, digest: &str) -> PathBuf {
+    objects_dir.join(&digest[..2]).join(&digest[2..])
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_: &std::fs::Metadata, _: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Replace `file` with a hard link to `object`. The link is created beside the file and renamed
+/// over it so that the file is never missing.
+fn replace_with_link(object: &Path, file: &Path) -> std::io::Result<()> {
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".fetch-source-link");
+    let tmp = PathBuf::from(tmp);
+    std::fs::hard_link(object, &tmp)?;
+    std::fs::rename(&tmp, file).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+/// Store each regular file in `dir` (recursively) in the object store, replacing files which
+/// are identical to an existing object with a hard link to it. Symlinks are left alone.
+pub(crate) fn deduplicate(objects_dir: &Path, dir: &Path) -> std::io::Result<DedupStats> {
+    let mut stats = DedupStats::default();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            stats += deduplicate(objects_dir, &entry.path())?;
+        } else if file_type.is_file() {
+            stats.files += 1;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            let object = object_path(objects_dir, &sha256::try_digest(&path)?);
+            match std::fs::metadata(&object) {
+                Ok(existing) => {
+                    // Linking would change the permissions of one of the files
+                    if is_same_file(&existing, &metadata)
+                        || existing.permissions() != metadata.permissions()
+                    {
+                        continue;
+                    }
+                    replace_with_link(&object, &path)?;
+                    stats.linked += 1;
+                    stats.bytes_saved += metadata.len();
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    // SAFETY: `object_path` always gives a path with a parent
+                    std::fs::create_dir_all(object.parent().unwrap())?;
+                    std::fs::hard_link(&path, &object)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Remove objects which are no longer linked from any artefact. Returns the number of objects
+/// removed.
+#[cfg(unix)]
+pub(crate) fn prune(objects_dir: &Path) -> std::io::Result<usize> {
+    use std::os::unix::fs::MetadataExt;
+    if !objects_dir.is_dir() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for subdir in std::fs::read_dir(objects_dir)? {
+        let subdir = subdir?.path();
+        for object in std::fs::read_dir(&subdir)? {
+            let object = object?;
+            if object.metadata()?.nlink() == 1 {
+                std::fs::remove_file(object.path())?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove objects which are no longer linked from any artefact. Link counts are not available on
+/// this platform, so no objects are removed.
+#[cfg(not(unix))]
+pub(crate) fn prune(_: &Path) -> std::io::Result<usize> {
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn identical_files_are_linked() {
+        let root = tempdir().unwrap();
+        let objects = root.path().join(OBJECTS_DIR);
+        let first = root.path().join("first");
+        let second = root.path().join("second");
+        for dir in [&first, &second] {
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(dir.join("src/lib.rs"), "// identical").unwrap();
+        }
+        std::fs::write(first.join("unique"), "first").unwrap();
+
+        let stats = deduplicate(&objects, &first).unwrap();
+        assert_eq!(stats.files, 2);
+        assert_eq!(stats.linked, 0);
+
+        let stats = deduplicate(&objects, &second).unwrap();
+        assert_eq!(stats.files, 1);
+        assert_eq!(stats.linked, 1);
+        assert_eq!(stats.bytes_saved, "// identical".len() as u64);
+        assert_eq!(
+            std::fs::read_to_string(second.join("src/lib.rs")).unwrap(),
+            "// identical"
+        );
+
+        // Deduplicating again changes nothing
+        let stats = deduplicate(&objects, &second).unwrap();
+        assert_eq!(stats.linked, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unlinked_objects_are_pruned() {
+        let root = tempdir().unwrap();
+        let objects = root.path().join(OBJECTS_DIR);
+        let artefact = root.path().join("artefact");
+        std::fs::create_dir_all(&artefact).unwrap();
+        std::fs::write(artefact.join("file"), "contents").unwrap();
+        deduplicate(&objects, &artefact).unwrap();
+        assert_eq!(prune(&objects).unwrap(), 0);
+
+        std::fs::remove_dir_all(&artefact).unwrap();
+        assert_eq!(prune(&objects).unwrap(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+//! Support for packing artefact directories into compressed archives and unpacking them again.
+
+use std::path::{Path, PathBuf};
+
+/// Append `suffix` to the file name of `path`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Pack the contents of `dir` into a gzipped tar archive at `archive`, then remove `dir`. The
+/// archive is written beside its final path and renamed into place once complete.
+pub(crate) fn pack(dir: &Path, archive: &Path) -> std::io::Result<()> {
+    let tmp = with_suffix(archive, ".tmp");
+    let file = std::fs::File::create(&tmp)?;
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::best());
+    let mut builder = ::tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    let result = builder
+        .append_dir_all(".", dir)
+        .and_then(|_| builder.into_inner())
+        .and_then(|encoder| encoder.finish())
+        .and_then(|_| std::fs::rename(&tmp, archive));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result?;
+    std::fs::remove_dir_all(dir)
+}
+
+/// Unpack the archive at `archive` into `dir`, then remove `archive`. The archive is unpacked
+/// beside `dir` and renamed into place once complete.
+pub(crate) fn unpack(archive: &Path, dir: &Path) -> std::io::Result<()> {
+    let tmp = with_suffix(dir, ".unpacking");
+    if tmp.exists() {
+        std::fs::remove_dir_all(&tmp)?;
+    }
+    let file = std::fs::File::open(archive)?;
+    let mut unpacker = ::tar::Archive::new(flate2::read::GzDecoder::new(file));
+    unpacker.set_preserve_permissions(true);
+    unpacker.set_preserve_mtime(true);
+    let result = unpacker
+        .unpack(&tmp)
+        .and_then(|_| std::fs::rename(&tmp, dir));
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+    result?;
+    std::fs::remove_file(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn pack_unpack_roundtrip() {
+        let root = tempdir().unwrap();
+        let dir = root.path().join("artefact");
+        let archive = root.path().join("artefact.tar.gz");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "// packed").unwrap();
+
+        pack(&dir, &archive).unwrap();
+        assert!(!dir.exists());
+        assert!(archive.is_file());
+
+        unpack(&archive, &dir).unwrap();
+        assert!(!archive.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("src/lib.rs")).unwrap(),
+            "// packed"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! JSON Schemas for the `fetch-source` metadata table, generated from the types which parse it, so
+//! that editors can validate and complete source definitions.
+
+use schemars::generate::SchemaSettings;
+use schemars::transform::{Transform, transform_subschemas};
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+
+/// A single platform or a list of platforms, as accepted by the `target` key
+#[derive(JsonSchema)]
+#[schemars(untagged)]
+#[allow(dead_code)]
+pub(crate) enum Platforms {
+    /// A target triple, e.g. `x86_64-pc-windows-msvc`, or a `cfg(...)` expression, e.g.
+    /// `cfg(target_os = "linux")`
+    One(String),
+    /// A list of target triples or `cfg(...)` expressions
+    Many(Vec<String>),
+}
+
+/// An entry in the `fetch-source` table
+#[derive(JsonSchema)]
+#[schemars(untagged)]
+#[allow(dead_code)]
+enum Entry {
+    /// Shorthand for a source: a git URL prefixed with `git+`, optionally followed by a
+    /// `?branch=`, `?tag=` or `?rev=` query and a `#recursive` fragment, or the URL of a `.tar.gz`
+    /// or `.tgz` archive
+    Shorthand(String),
+    #[cfg(feature = "tar")]
+    /// A remote tar archive
+    Tar(TarEntry),
+    /// A remote git repository
+    Git(GitEntry),
+}
+
+/// A remote tar archive and its options
+#[cfg(feature = "tar")]
+#[derive(JsonSchema)]
+#[schemars(deny_unknown_fields)]
+#[allow(dead_code)]
+struct TarEntry {
+    #[schemars(flatten)]
+    source: crate::Tar,
+    #[schemars(flatten)]
+    options: crate::SourceOptions,
+}
+
+/// A remote git repository and its options
+#[derive(JsonSchema)]
+#[schemars(deny_unknown_fields)]
+#[allow(dead_code)]
+struct GitEntry {
+    #[schemars(flatten)]
+    source: crate::Git,
+    #[schemars(flatten)]
+    options: crate::SourceOptions,
+}
+
+/// The sources declared in a `fetch-source` table, keyed by name
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct SourcesTable(std::collections::BTreeMap<String, Entry>);
+
+/// The JSON Schema of a `fetch-source` table, i.e. the contents of
+/// `[package.metadata.fetch-source]` or `[workspace.metadata.fetch-source]`.
+pub fn table_schema() -> Schema {
+    generator().into_root_schema_for::<SourcesTable>()
+}
+
+/// The JSON Schema of a `Cargo.toml` manifest which only describes the
+/// `[package.metadata.fetch-source]` and `[workspace.metadata.fetch-source]` tables. Everything else
+/// in the manifest is accepted as-is.
+pub fn manifest_schema() -> Schema {
+    let mut generator = generator();
+    let table = generator.subschema_for::<SourcesTable>();
+    let metadata = json_schema!({
+        "type": "object",
+        "properties": {
+            "metadata": {
+                "type": "object",
+                "properties": { "fetch-source": table },
+            },
+        },
+    });
+    let mut schema = json_schema!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Cargo.toml",
+        "description": "Sources declared for cargo-fetch-source",
+        "type": "object",
+        "properties": {
+            "package": metadata,
+            "workspace": metadata,
+        },
+    });
+    let definitions = generator.take_definitions(true);
+    if !definitions.is_empty() {
+        schema.insert("$defs".into(), definitions.into());
+    }
+    schema
+}
+
+/// A schema generator for TOML, which has no `null`
+fn generator() -> SchemaGenerator {
+    SchemaSettings::draft2020_12()
+        .with_transform(RemoveNull)
+        .into_generator()
+}
+
+/// Remove `null` from the types of optional keys, which are omitted rather than `null` in TOML
+#[derive(Clone)]
+struct RemoveNull;
+
+impl Transform for RemoveNull {
+    fn transform(&mut self, schema: &mut Schema) {
+        if let Some(serde_json::Value::Array(types)) = schema.get_mut("type") {
+            types.retain(|t| t != "null");
+            if types.len() == 1 {
+                let single = types.remove(0);
+                schema.insert("type".into(), single);
+            }
+        }
+        transform_subschemas(self, schema);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names of the properties of a schema
+    fn properties(schema: &serde_json::Value) -> Vec<&str> {
+        let mut names = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn entries_describe_every_key() {
+        let schema = serde_json::to_value(table_schema()).unwrap();
+        let git = &schema["$defs"]["GitEntry"];
+        // The flattened git reference can only be excluded with `unevaluatedProperties`
+        assert_eq!(git["unevaluatedProperties"], false);
+        assert!(properties(git).contains(&"git"));
+        assert!(properties(git).contains(&"recursive"));
+        assert!(properties(git).contains(&"dest"));
+        assert!(properties(git).contains(&"target"));
+        assert_eq!(git["properties"]["dest"]["type"], "string");
+        #[cfg(feature = "tar")]
+        {
+            let tar = &schema["$defs"]["TarEntry"];
+            assert_eq!(tar["additionalProperties"], false);
+            assert_eq!(
+                properties(tar),
+                ["dest", "features", "sha256", "tar", "target", "version"]
+            );
+        }
+    }
+
+    #[test]
+    fn manifest_schema_refers_to_the_table() {
+        let schema = serde_json::to_value(manifest_schema()).unwrap();
+        let table = &schema["properties"]["package"]["properties"]["metadata"]["properties"]["fetch-source"];
+        assert_eq!(table["$ref"], "#/$defs/SourcesTable");
+        assert!(schema["$defs"]["SourcesTable"].is_object());
+    }
+}
@@ -2,6 +2,7 @@
 
 use super::error::FetchError;
 use super::git::{Git, GitReference};
+use super::interpolate::Variables;
 #[cfg(feature = "tar")]
 use super::tar::Tar;
 
@@ -38,13 +39,112 @@ pub enum SourceParseError {
         requires: String,
     },
 
-    /// A toml value was expected to be a table.
-    #[error("expected value '{name}' to be a toml table")]
+    /// A source has a key which isn't recognised.
+    #[error(
+        "source '{source_name}' has unknown key '{key}'{}",
+        did_you_mean(suggestion)
+    )]
+    KeyUnknown {
+        /// The name of the source
+        source_name: SourceName,
+        /// The unknown key
+        key: String,
+        /// A known key which is similar to the unknown one, if any
+        suggestion: Option<String>,
+    },
+
+    /// A source has a key which belongs to a different source type.
+    #[error("source '{source_name}' has key '{key}' which is not valid for a {variant} source")]
+    KeyInvalid {
+        /// The name of the source
+        source_name: SourceName,
+        /// The offending key
+        key: String,
+        /// The type of the source
+        variant: String,
+    },
+
+    /// A git source has more than one of `branch`, `tag` and `rev`.
+    #[error(
+        "source '{source_name}' has conflicting keys '{key}' and '{other}': expected at most one of: branch, tag, rev"
+    )]
+    GitReferenceConflict {
+        /// The name of the source
+        source_name: SourceName,
+        /// The first reference key
+        key: String,
+        /// The conflicting reference key
+        other: String,
+    },
+
+    /// A toml value was expected to be a table, or a string in shorthand form.
+    #[error("expected value '{name}' to be a toml table or a string")]
     ValueNotTable {
         /// The key for the value which was expected to be a table
         name: String,
     },
 
+    /// A source given as a string isn't valid shorthand for a source.
+    #[error("source '{source_name}' has invalid shorthand '{value}': {reason}")]
+    ShorthandInvalid {
+        /// The name of the source
+        source_name: SourceName,
+        /// The shorthand string
+        value: String,
+        /// Why the shorthand is invalid
+        reason: String,
+    },
+
+    /// A source would be copied outside of the output directory.
+    #[error("source '{source_name}' has destination '{}' which is not a relative path within the output directory", dest.display())]
+    DestinationUnsafe {
+        /// The name of the source
+        source_name: SourceName,
+        /// The offending destination
+        dest: std::path::PathBuf,
+    },
+
+    /// Two sources would be copied to the same place, or one inside the other.
+    #[error("sources '{source_name}' and '{other}' have overlapping destinations '{}' and '{}'", dest.display(), other_dest.display())]
+    DestinationOverlap {
+        /// The name of the source with the outer destination
+        source_name: SourceName,
+        /// Its destination
+        dest: std::path::PathBuf,
+        /// The name of the source with the same or a nested destination
+        other: SourceName,
+        /// Its destination
+        other_dest: std::path::PathBuf,
+    },
+
+    /// A source is declared more than once with different definitions, e.g. by two members of a
+    /// workspace.
+    #[error("source '{source_name}' is declared more than once with different definitions")]
+    SourceConflict {
+        /// The name of the source
+        source_name: SourceName,
+    },
+
+    /// A source refers to a variable which isn't defined.
+    #[error("source '{source_name}' uses variable '${{{variable}}}' which is not defined")]
+    VariableUndefined {
+        /// The name of the source
+        source_name: SourceName,
+        /// The name of the variable
+        variable: String,
+    },
+
+    /// A source has a `${` without a closing `}`.
+    #[error("source '{source_name}' has a '${{' without a closing '}}'")]
+    VariableUnterminated {
+        /// The name of the source
+        source_name: SourceName,
+    },
+
+    /// The `workspace` table was not found.
+    #[error("required table 'workspace' not found")]
+    WorkspaceTableNotFound,
+
     /// The `package.metadata.fetch-source` table was not found.
     #[error("required table 'package.metadata.fetch-source' not found in string")]
     SourceTableNotFound,
@@ -58,13 +158,56 @@ pub enum SourceParseError {
     JsonInvalid(#[from] serde_json::Error),
 }
 
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(", did you mean '{suggestion}'?"),
+        None => String::new(),
+    }
+}
+
+/// The number of single-character insertions, deletions, substitutions and adjacent
+/// transpositions needed to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The candidate most similar to `key`, if any is similar enough to be a likely typo
+fn suggest<'a>(key: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let max_distance = key.chars().count().max(3) / 3;
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
 /// Represents the result of a fetch operation
 pub type FetchResult<T> = Result<T, crate::FetchError>;
 
 /// Represents a source that has been fetched from a remote location.
 ///
 /// Notably implements [`AsRef<std::path::Path>`](std::path::Path) and [`AsRef<Source>`](Source).
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 pub struct Artefact {
     // This is a combination of the fetched artefact and the source it was fetched from.
     // Note that the name associated with a source *must not* be stored in the cache. This avoids
@@ -73,9 +216,63 @@ pub struct Artefact {
     source: Source,
     /// The local copy
     path: std::path::PathBuf,
+    /// When the source was fetched, in seconds since the Unix epoch. Unknown for artefacts cached
+    /// by earlier versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fetched: Option<u64>,
+    /// When the artefact was last accessed through a cache, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accessed: Option<u64>,
+}
+
+/// The current time in seconds since the Unix epoch, if the system clock is sane.
+fn unix_time_now() -> Option<u64> {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .ok()
+}
+
+fn from_unix_time(secs: u64) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)
 }
 
 impl Artefact {
+    pub(crate) fn new(source: Source, path: std::path::PathBuf) -> Self {
+        Self {
+            source,
+            path,
+            fetched: None,
+            accessed: None,
+        }
+    }
+
+    /// Move an artefact to a new path, keeping its other metadata.
+    pub(crate) fn relocate(self, path: std::path::PathBuf) -> Self {
+        Self { path, ..self }
+    }
+
+    /// Record that the artefact was accessed now.
+    pub(crate) fn touch(&mut self) {
+        self.accessed = unix_time_now();
+    }
+
+    /// When the source was fetched, if known
+    pub fn fetched(&self) -> Option<std::time::SystemTime> {
+        self.fetched.map(from_unix_time)
+    }
+
+    /// When the artefact was last accessed through a [`Cache`](crate::Cache), if known
+    pub fn accessed(&self) -> Option<std::time::SystemTime> {
+        self.accessed.map(from_unix_time)
+    }
+
+    /// When the artefact was last used, i.e. when it was last accessed or else when it was
+    /// fetched, if known
+    pub fn last_used(&self) -> Option<std::time::SystemTime> {
+        self.accessed().or_else(|| self.fetched())
+    }
+
     /// Get the path to an artefact
     pub fn path(&self) -> &std::path::Path {
         &self.path
@@ -144,8 +341,19 @@ impl SourceVariant {
             Self::Git => None,
         }
     }
+
+    /// The keys which define a source of this type
+    fn keys(&self) -> &'static [&'static str] {
+        match self {
+            Self::Tar => &["tar", "sha256"],
+            Self::Git => &["git", "branch", "tag", "rev", "recursive"],
+        }
+    }
 }
 
+/// The keys which select a git reference, at most one of which may be given
+const GIT_REFERENCE_KEYS: &[&str] = &["branch", "tag", "rev"];
+
 /// The digest associated with the definition of a [`Source`]
 #[derive(
     Debug,
@@ -168,7 +376,7 @@ impl AsRef<str> for Digest {
 }
 
 /// Represents an entry in the `package.metadata.fetch-source` table.
-#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 #[serde(untagged)]
 pub enum Source {
     #[cfg(feature = "tar")]
@@ -194,9 +402,7 @@ impl Source {
     #[cfg(feature = "tar")]
     /// Create a source representing a remote tar archive
     pub fn tar<S: AsRef<str>>(url: S) -> Self {
-        Self::Tar(Tar {
-            url: url.as_ref().to_string(),
-        })
+        Self::Tar(Tar::new(url))
     }
 
     /// Create a source representing a remote git repository
@@ -220,7 +426,12 @@ impl Source {
             Source::Git(ref git) => git.fetch(dest),
         };
         match result {
-            Ok(path) => Ok(Artefact { source: self, path }),
+            Ok(path) => Ok(Artefact {
+                source: self,
+                path,
+                fetched: unix_time_now(),
+                accessed: None,
+            }),
             Err(err) => Err(FetchError::new(err, self)),
         }
     }
@@ -257,39 +468,321 @@ impl Source {
         })
     }
 
+    /// Check that every key belongs to the detected source type, and that a git source selects
+    /// at most one reference.
+    fn enforce_known_keys<S: ToString>(
+        name: S,
+        variant: &SourceVariant,
+        source: &toml::Table,
+    ) -> Result<(), SourceParseError> {
+        for key in source.keys() {
+            if variant.keys().contains(&key.as_str()) {
+                continue;
+            }
+            if SOURCE_VARIANTS
+                .iter()
+                .any(|v| v.keys().contains(&key.as_str()))
+            {
+                return Err(SourceParseError::KeyInvalid {
+                    source_name: name.to_string(),
+                    key: key.clone(),
+                    variant: variant.to_string(),
+                });
+            }
+            let candidates = variant.keys().iter().chain(SourceOptions::KEYS).copied();
+            return Err(SourceParseError::KeyUnknown {
+                source_name: name.to_string(),
+                key: key.clone(),
+                suggestion: suggest(key, candidates),
+            });
+        }
+        let mut references = GIT_REFERENCE_KEYS
+            .iter()
+            .filter(|key| source.contains_key(**key));
+        if let (Some(key), Some(other)) = (references.next(), references.next()) {
+            return Err(SourceParseError::GitReferenceConflict {
+                source_name: name.to_string(),
+                key: key.to_string(),
+                other: other.to_string(),
+            });
+        }
+        Ok(())
+    }
+
     /// Parse a TOML table into a `Source` instance. Exactly one key in the table must identify
-    /// a valid, enabled source type, otherwise an error is returned.
-    pub fn parse<S: ToString>(name: S, source: toml::Table) -> Result<Self, SourceParseError> {
-        Self::enforce_one_valid_variant(name, &source)?;
+    /// a valid, enabled source type, and every other key must belong to that type, otherwise an
+    /// error is returned. Keys which configure [`SourceOptions`] are ignored.
+    pub fn parse<S: ToString>(name: S, mut source: toml::Table) -> Result<Self, SourceParseError> {
+        source.retain(|key, _| !SourceOptions::KEYS.contains(&key));
+        let variant = Self::enforce_one_valid_variant(name.to_string(), &source)?;
+        Self::enforce_known_keys(name, &variant, &source)?;
         Ok(toml::Value::Table(source).try_into::<Self>()?)
     }
 }
 
+/// Settings which control how a source is used rather than what is fetched. These are declared
+/// alongside the source in the `package.metadata.fetch-source` table but don't contribute to its
+/// [`Digest`].
+#[derive(Debug, Default, serde::Deserialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SourceOptions {
+    /// Where to copy the source to, relative to the output directory
+    dest: Option<std::path::PathBuf>,
+    /// The cargo features which enable the source. If empty, the source is always enabled.
+    #[serde(default)]
+    features: Vec<String>,
+    /// The platforms the source is needed for. If empty, the source is needed for every platform.
+    #[serde(default, deserialize_with = "crate::cfg::deserialize_platforms")]
+    #[cfg_attr(feature = "schema", schemars(with = "crate::schema::Platforms"))]
+    target: Vec<crate::Platform>,
+    /// The value of `${version}` in the source's other values
+    version: Option<String>,
+}
+
+impl SourceOptions {
+    /// The keys of a source's table which configure its options
+    const KEYS: &[&str] = &["dest", "features", "target", "version"];
+
+    /// The explicit destination of the source, if any
+    pub fn dest(&self) -> Option<&std::path::Path> {
+        self.dest.as_deref()
+    }
+
+    /// The cargo features which enable the source, any one of which is sufficient. A source
+    /// without features is always enabled. See [`Conditions`](crate::Conditions).
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// The platforms the source is needed for, any one of which is sufficient. A source without
+    /// targets is needed for every platform. See [`Conditions`](crate::Conditions).
+    pub fn targets(&self) -> &[crate::Platform] {
+        &self.target
+    }
+
+    /// The version of the source, which `${version}` expands to in its other values
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Where the source called `name` should be copied to, relative to the output directory. This
+    /// is the explicit destination if given, otherwise it is derived from the name with
+    /// [`Source::as_path_component`].
+    pub fn destination<S: AsRef<str>>(&self, name: S) -> std::path::PathBuf {
+        self.dest
+            .clone()
+            .unwrap_or_else(|| Source::as_path_component(name))
+    }
+
+    /// Parse the options from a source's TOML table, ignoring the keys which define the source.
+    pub fn parse(source: &toml::Table) -> Result<Self, SourceParseError> {
+        let options = source
+            .iter()
+            .filter(|(key, _)| Self::KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<toml::Table>();
+        Ok(toml::Value::Table(options).try_into::<Self>()?)
+    }
+}
+
 /// Represents the contents of the `package.metadata.fetch-source` table in a `Cargo.toml` file.
 pub type SourcesTable = std::collections::HashMap<SourceName, Source>;
 
+/// The [`SourceOptions`] of every source in the `package.metadata.fetch-source` table.
+pub type SourceOptionsTable = std::collections::HashMap<SourceName, SourceOptions>;
+
 /// Parse a `package.metadata.fetch-source` table into a [`SourcesTable`](crate::source::SourcesTable) map
 pub fn try_parse(table: &toml::Table) -> Result<SourcesTable, SourceParseError> {
+    try_parse_with_options(table, &Variables::from_env()).map(|(sources, _)| sources)
+}
+
+/// Parse a `package.metadata.fetch-source` table into a [`SourcesTable`] map and the
+/// [`SourceOptions`] of each source. Variables such as `${version}` are expanded first.
+pub fn try_parse_with_options(
+    table: &toml::Table,
+    variables: &Variables,
+) -> Result<(SourcesTable, SourceOptionsTable), SourceParseError> {
+    let mut sources = SourcesTable::new();
+    let mut options = SourceOptionsTable::new();
+    for (name, value) in table {
+        let value = match value {
+            toml::Value::Table(table) => table.clone(),
+            toml::Value::String(shorthand) => parse_shorthand(name, shorthand)?,
+            _ => {
+                return Err(SourceParseError::ValueNotTable {
+                    name: name.to_owned(),
+                });
+            }
+        };
+        let value = crate::interpolate::interpolate(name, &value, variables)?;
+        options.insert(name.to_owned(), SourceOptions::parse(&value)?);
+        sources.insert(name.to_owned(), Source::parse(name, value)?);
+    }
+    check_all_destinations(&sources, &options)?;
+    Ok((sources, options))
+}
+
+/// The file extensions of archives which can be given in shorthand
+const SHORTHAND_ARCHIVE_EXTENSIONS: &[&str] = &[".tar.gz", ".tgz"];
+
+/// Expand a source given as a string into the equivalent table. The string is either:
+/// - a git URL prefixed with `git+`, optionally followed by one `?branch=`, `?tag=` or `?rev=`
+///   query and a `#recursive` fragment, e.g. `git+https://host/repo.git?tag=v1#recursive`;
+/// - the URL of a `.tar.gz` or `.tgz` archive.
+fn parse_shorthand(name: &str, shorthand: &str) -> Result<toml::Table, SourceParseError> {
+    let invalid = |reason: String| SourceParseError::ShorthandInvalid {
+        source_name: name.to_string(),
+        value: shorthand.to_string(),
+        reason,
+    };
+    let mut table = toml::Table::new();
+    if let Some(url) = shorthand.strip_prefix("git+") {
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (url, None),
+        };
+        let (url, query) = match url.split_once('?') {
+            Some((url, query)) => (url, Some(query)),
+            None => (url, None),
+        };
+        if url.is_empty() {
+            return Err(invalid("missing git URL".to_string()));
+        }
+        table.insert("git".into(), url.into());
+        if let Some(query) = query {
+            let Some((key, value)) = query.split_once('=') else {
+                return Err(invalid(format!(
+                    "expected a git reference, found '{query}'"
+                )));
+            };
+            if !["branch", "tag", "rev"].contains(&key) {
+                return Err(invalid(format!(
+                    "unknown git reference '{key}', expected one of: branch, tag, rev"
+                )));
+            }
+            if value.contains('&') {
+                return Err(invalid("expected at most one git reference".to_string()));
+            }
+            table.insert(key.into(), value.into());
+        }
+        match fragment {
+            Some("recursive") => {
+                table.insert("recursive".into(), true.into());
+            }
+            Some(fragment) => {
+                return Err(invalid(format!(
+                    "unknown fragment '{fragment}', expected 'recursive'"
+                )));
+            }
+            None => {}
+        }
+    } else {
+        let path = shorthand.split(['?', '#']).next().unwrap_or_default();
+        if !SHORTHAND_ARCHIVE_EXTENSIONS
+            .iter()
+            .any(|extension| path.ends_with(extension))
+        {
+            return Err(invalid(format!(
+                "expected a 'git+' URL or the URL of an archive ending in one of: {}",
+                SHORTHAND_ARCHIVE_EXTENSIONS.join(", ")
+            )));
+        }
+        table.insert("tar".into(), shorthand.into());
+    }
+    Ok(table)
+}
+
+/// Check that the paths derived from the names of the sources, and their destinations, are safe
+/// and don't overlap.
+pub(crate) fn check_all_destinations(
+    sources: &SourcesTable,
+    options: &SourceOptionsTable,
+) -> Result<(), SourceParseError> {
+    // Names always map onto a path, e.g. when vendoring, even if a source has a destination
+    check_destinations(
+        sources
+            .keys()
+            .map(|name| (name, Source::as_path_component(name))),
+    )?;
+    check_destinations(
+        options
+            .iter()
+            .map(|(name, options)| (name, options.destination(name))),
+    )
+}
+
+/// Check that every destination is a relative path without `..` components and that no
+/// destination is the same as, or nested inside, another.
+fn check_destinations<'a, I>(destinations: I) -> Result<(), SourceParseError>
+where
+    I: IntoIterator<Item = (&'a SourceName, std::path::PathBuf)>,
+{
+    let mut destinations = destinations.into_iter().collect::<Vec<_>>();
+    for (name, dest) in &destinations {
+        let is_safe = dest.components().next().is_some()
+            && dest
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if !is_safe {
+            return Err(SourceParseError::DestinationUnsafe {
+                source_name: (*name).clone(),
+                dest: dest.clone(),
+            });
+        }
+    }
+    // Paths are ordered component-wise so a nested path always sorts directly after its parent
+    // or a sibling nested inside the same parent
+    destinations.sort_by(|(a_name, a), (b_name, b)| a.cmp(b).then(a_name.cmp(b_name)));
+    for pair in destinations.windows(2) {
+        let [(name, dest), (other, other_dest)] = pair else {
+            unreachable!()
+        };
+        if other_dest.starts_with(dest) {
+            return Err(SourceParseError::DestinationOverlap {
+                source_name: (*name).clone(),
+                dest: dest.clone(),
+                other: (*other).clone(),
+                other_dest: other_dest.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Get the `package.metadata.fetch-source` table from the contents of a Cargo.toml file.
+fn sources_table(toml_str: &str) -> Result<toml::Table, SourceParseError> {
+    let mut table = toml_str.parse::<toml::Table>()?;
     table
-        .iter()
-        .map(|(k, v)| match v.as_table() {
-            Some(t) => Source::parse(k, t.to_owned()).map(|s| (k.to_owned(), s)),
-            None => Err(SourceParseError::ValueNotTable { name: k.to_owned() }),
-        })
-        .collect()
+        .remove("package")
+        .and_then(|v| v.get("metadata").cloned())
+        .and_then(|v| v.get("fetch-source").cloned())
+        .and_then(|v| v.as_table().cloned())
+        .ok_or(SourceParseError::SourceTableNotFound)
 }
 
 /// Parse the contents of a Cargo.toml file containing the `package.metadata.fetch-source` table
 /// into a [`SourcesTable`](crate::source::SourcesTable) map.
 pub fn try_parse_toml<S: AsRef<str>>(toml_str: S) -> Result<SourcesTable, SourceParseError> {
-    let table = toml_str.as_ref().parse::<toml::Table>()?;
-    let sources_table = table
-        .get("package")
-        .and_then(|v| v.get("metadata"))
-        .and_then(|v| v.get("fetch-source"))
-        .and_then(|v| v.as_table())
-        .ok_or(SourceParseError::SourceTableNotFound)?;
-    try_parse(sources_table)
+    try_parse(&sources_table(toml_str.as_ref())?)
+}
+
+/// Parse the contents of a Cargo.toml file containing the `package.metadata.fetch-source` table
+/// into a [`SourcesTable`] map and the [`SourceOptions`] of each source. `${target}` is read from
+/// the `TARGET` environment variable, as in a build script.
+pub fn try_parse_toml_with_options<S: AsRef<str>>(
+    toml_str: S,
+) -> Result<(SourcesTable, SourceOptionsTable), SourceParseError> {
+    try_parse_toml_with_variables(toml_str, &Variables::from_env())
+}
+
+/// Parse the contents of a Cargo.toml file containing the `package.metadata.fetch-source` table
+/// into a [`SourcesTable`] map and the [`SourceOptions`] of each source, expanding the given
+/// [`Variables`].
+pub fn try_parse_toml_with_variables<S: AsRef<str>>(
+    toml_str: S,
+    variables: &Variables,
+) -> Result<(SourcesTable, SourceOptionsTable), SourceParseError> {
+    try_parse_with_options(&sources_table(toml_str.as_ref())?, variables)
 }
 
 #[cfg(test)]
@@ -363,6 +856,89 @@ mod test_parsing_single_source_value {
     }
 }
 
+#[cfg(test)]
+mod test_parsing_unknown_keys {
+    use super::*;
+
+    fn parse_error(source: toml::Table) -> SourceParseError {
+        Source::parse("src", source).unwrap_err()
+    }
+
+    #[test]
+    fn misspelt_keys_suggest_known_keys() {
+        for (source, typo, expected) in [
+            (
+                toml::toml! { git = "git@github.com:foo/bar.git"  brnach = "dev" },
+                "brnach",
+                "branch",
+            ),
+            (
+                toml::toml! { git = "git@github.com:foo/bar.git"  recursve = true },
+                "recursve",
+                "recursive",
+            ),
+        ] {
+            assert!(matches!(
+                parse_error(source),
+                KeyUnknown { source_name, key, suggestion: Some(suggestion) }
+                    if source_name == "src" && key == typo && suggestion == expected
+            ));
+        }
+    }
+
+    #[test]
+    fn unrelated_keys_have_no_suggestion() {
+        assert!(matches!(
+            parse_error(toml::toml! { git = "git@github.com:foo/bar.git"  colour = "blue" }),
+            KeyUnknown { key, suggestion: None, .. } if key == "colour"
+        ));
+    }
+
+    #[test]
+    fn keys_of_other_source_types_fail() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            src = { git = "git@github.com:foo/bar.git", tar = "https://example.com/foo.tar.gz" }
+        "#;
+        // Two source types are still reported as such
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(VariantMultiple { .. })
+        ));
+        #[cfg(feature = "tar")]
+        assert!(matches!(
+            parse_error(toml::toml! { tar = "https://example.com/foo.tar.gz"  branch = "dev" }),
+            KeyInvalid { key, variant, .. } if key == "branch" && variant == "tar"
+        ));
+    }
+
+    #[test]
+    fn conflicting_git_references_fail() {
+        let err = parse_error(toml::toml! {
+            git = "git@github.com:foo/bar.git"
+            branch = "dev"
+            tag = "v1"
+        });
+        assert!(matches!(
+            &err,
+            GitReferenceConflict { source_name, key, other }
+                if source_name == "src" && key == "branch" && other == "tag"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "source 'src' has conflicting keys 'branch' and 'tag': expected at most one of: branch, tag, rev"
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_once() {
+        assert_eq!(edit_distance("branch", "branch"), 0);
+        assert_eq!(edit_distance("brnach", "branch"), 1);
+        assert_eq!(edit_distance("recursve", "recursive"), 1);
+        assert_eq!(edit_distance("", "tag"), 3);
+    }
+}
+
 #[cfg(test)]
 mod test_parsing_sources_table_failure_modes {
     use super::*;
@@ -394,7 +970,7 @@ mod test_parsing_sources_table_failure_modes {
             name = "my_fun_test_suite"
 
             [package.metadata.fetch-source]
-            not-a-table = "actually a string"
+            not-a-table = 42
         "#;
         assert!(matches!(
             try_parse_toml(document),
@@ -452,3 +1028,179 @@ mod test_parsing_sources_table_failure_modes {
         ));
     }
 }
+
+#[cfg(test)]
+mod test_parsing_shorthand {
+    use super::*;
+
+    #[test]
+    fn parse_git_shorthand() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            plain = "git+https://example.com/repo.git"
+            tagged = "git+https://example.com/repo.git?tag=v1#recursive"
+            long = { git = "https://example.com/repo.git", tag = "v1", recursive = true }
+        "#;
+        let sources = try_parse_toml(document).unwrap();
+        assert_eq!(
+            sources["plain"],
+            Source::git("https://example.com/repo.git", None, false)
+        );
+        assert_eq!(sources["tagged"], sources["long"]);
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn parse_archive_shorthand() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            short = "https://example.com/foo.tar.gz"
+            long = { tar = "https://example.com/foo.tar.gz" }
+        "#;
+        let sources = try_parse_toml(document).unwrap();
+        assert_eq!(sources["short"], sources["long"]);
+    }
+
+    #[test]
+    fn parse_invalid_shorthand_fails() {
+        for shorthand in [
+            "actually a string",
+            "https://example.com/foo.zip",
+            "git+",
+            "git+https://example.com/repo.git?commit=abc",
+            "git+https://example.com/repo.git?tag=v1&branch=dev",
+            "git+https://example.com/repo.git#shallow",
+        ] {
+            let document = format!("[package.metadata.fetch-source]\nfoo = {shorthand:?}\n");
+            assert!(
+                matches!(
+                    try_parse_toml(document),
+                    Err(ShorthandInvalid { source_name, value, .. })
+                        if source_name == "foo" && value == shorthand
+                ),
+                "{shorthand}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_parsing_source_options {
+    use super::*;
+
+    #[test]
+    fn parse_dest_overrides_destination() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            "vendor::zlib::1.3" = { git = "git@github.com:madler/zlib.git", dest = "third_party/zlib" }
+            "vendor::foo" = { git = "git@github.com:foo/bar.git" }
+        "#;
+        let (_, options) = try_parse_toml_with_options(document).unwrap();
+        assert_eq!(
+            options["vendor::zlib::1.3"].destination("vendor::zlib::1.3"),
+            std::path::Path::new("third_party/zlib")
+        );
+        assert_eq!(
+            options["vendor::foo"].destination("vendor::foo"),
+            std::path::Path::new("vendor/foo")
+        );
+    }
+
+    #[test]
+    fn dest_does_not_change_digest() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            with-dest = { git = "git@github.com:foo/bar.git", dest = "somewhere/else" }
+            without-dest = { git = "git@github.com:foo/bar.git" }
+        "#;
+        let sources = try_parse_toml(document).unwrap();
+        assert_eq!(sources["with-dest"], sources["without-dest"]);
+        assert_eq!(
+            Source::digest(&sources["with-dest"]),
+            Source::digest(&sources["without-dest"])
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_parsing_source_destinations {
+    use super::*;
+
+    #[test]
+    fn nested_names_fail() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "git@github.com:foo/foo.git" }
+            "foo::bar" = { git = "git@github.com:foo/bar.git" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(DestinationOverlap { source_name, other, .. })
+                if source_name == "foo" && other == "foo::bar"
+        ));
+    }
+
+    #[test]
+    fn sibling_names_succeed() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            "foo::bar" = { git = "git@github.com:foo/bar.git" }
+            "foo::baz" = { git = "git@github.com:foo/baz.git" }
+            foo-qux = { git = "git@github.com:foo/qux.git" }
+        "#;
+        assert!(try_parse_toml(document).is_ok());
+    }
+
+    #[test]
+    fn identical_dests_fail() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "git@github.com:foo/foo.git", dest = "third_party/foo" }
+            bar = { git = "git@github.com:foo/bar.git", dest = "third_party/foo" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(DestinationOverlap { source_name, other, .. })
+                if source_name == "bar" && other == "foo"
+        ));
+    }
+
+    #[test]
+    fn dest_nested_in_name_fails() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "git@github.com:foo/foo.git" }
+            bar = { git = "git@github.com:foo/bar.git", dest = "foo/bar" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(DestinationOverlap { source_name, other, .. })
+                if source_name == "foo" && other == "bar"
+        ));
+    }
+
+    #[test]
+    fn parent_dir_in_name_fails() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            "..::escape" = { git = "git@github.com:foo/foo.git" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(DestinationUnsafe { source_name, .. }) if source_name == "..::escape"
+        ));
+    }
+
+    #[test]
+    fn absolute_dest_fails() {
+        let document = r#"
+            [package.metadata.fetch-source]
+            foo = { git = "git@github.com:foo/foo.git", dest = "/etc/foo" }
+        "#;
+        assert!(matches!(
+            try_parse_toml(document),
+            Err(DestinationUnsafe { source_name, dest })
+                if source_name == "foo" && dest == std::path::Path::new("/etc/foo")
+        ));
+    }
+}
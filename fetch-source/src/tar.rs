@@ -4,17 +4,47 @@ use super::error::FetchErrorKind;
 
 /// Represents a remote tar archive to be downloaded and extracted.
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Tar {
+    /// The URL of the archive
     #[serde(rename = "tar")]
     pub(crate) url: String,
+    /// The expected SHA-256 digest of the archive, as a hex string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sha256: Option<String>,
 }
 
 impl Tar {
+    /// Create a new tar archive with the given URL and no expected digest.
+    pub fn new<S: AsRef<str>>(url: S) -> Self {
+        Self {
+            url: url.as_ref().to_string(),
+            sha256: None,
+        }
+    }
+
     /// The upstream URL.
     pub fn upstream(&self) -> &str {
         &self.url
     }
 
+    /// The expected SHA-256 digest of the archive, if given.
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    /// Download the archive and compute its SHA-256 digest, e.g. to give it as the expected
+    /// digest.
+    pub fn download_sha256(&self) -> crate::FetchResult<String> {
+        self.download()
+            .map(|bytes| sha256::digest(bytes.as_ref()))
+            .map_err(|err| crate::FetchError::new(err, crate::Source::Tar(self.clone())))
+    }
+
+    fn download(&self) -> Result<impl AsRef<[u8]>, FetchErrorKind> {
+        Ok(reqwest::blocking::get(self.url.clone())?.bytes()?)
+    }
+
     /// Download and extract the tar archive directly into `dir`.
     pub(crate) fn fetch<P: AsRef<std::path::Path>>(
         &self,
@@ -24,7 +54,16 @@ impl Tar {
         if !dir.exists() {
             std::fs::create_dir_all(dir)?;
         }
-        let bytes = reqwest::blocking::get(self.url.clone())?.bytes()?;
+        let bytes = self.download()?;
+        if let Some(expected) = &self.sha256 {
+            let actual = sha256::digest(bytes.as_ref());
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(FetchErrorKind::ChecksumMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
         let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bytes.as_ref()));
         // Unpack the contents of the archive directly into the provided directory
         archive.unpack(dir)?;
@@ -37,3 +76,71 @@ impl std::fmt::Display for Tar {
         write!(f, "{}", self.url)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    /// A gzipped tar archive containing a single file
+    fn archive() -> Vec<u8> {
+        let contents = b"// fetched";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        builder
+            .append_data(&mut header, "lib.rs", contents.as_slice())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Serve `body` to a single request, returning its URL
+    fn serve_once(body: Vec<u8>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/archive.tar.gz", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn fetch_verifies_checksum() {
+        let body = archive();
+        let digest = sha256::digest(body.as_slice());
+        let dir = tempfile::tempdir().unwrap();
+        let tar = Tar {
+            url: serve_once(body),
+            sha256: Some(digest.to_uppercase()),
+        };
+        tar.fetch(dir.path()).unwrap();
+        assert!(dir.path().join("lib.rs").is_file());
+    }
+
+    #[test]
+    fn fetch_fails_for_mismatched_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = Tar {
+            url: serve_once(archive()),
+            sha256: Some("0".repeat(64)),
+        };
+        let err = tar.fetch(dir.path()).unwrap_err();
+        assert!(
+            matches!(&err, FetchErrorKind::ChecksumMismatch { expected, .. } if *expected == "0".repeat(64)),
+            "{err}"
+        );
+        assert!(!dir.path().join("lib.rs").exists());
+    }
+}
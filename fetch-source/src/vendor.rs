@@ -0,0 +1,233 @@
+//! Support for vendoring sources into a project-local directory.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::{Artefact, Source, SourceName, SourcesTable};
+
+const VENDOR_FILE_NAME: &str = "fetch-source-vendor.json";
+
+/// The default directory, relative to the directory containing `Cargo.toml`, where sources are
+/// vendored.
+pub const DEFAULT_VENDOR_DIR: &str = "fetch-source-vendor";
+
+/// Records which sources have been vendored into a directory and where within it each one was
+/// copied.
+///
+/// Unlike a [`Cache`](crate::Cache), vendored sources are recorded by name: a vendored copy is only
+/// used for a source when both the name and the definition of the source match.
+#[derive(Debug)]
+pub struct Vendor {
+    items: BTreeMap<SourceName, Artefact>,
+    vendor_file: PathBuf,
+}
+
+impl Vendor {
+    /// Normalise to the path of a vendor file. We rely on `canonicalize` to error when the
+    /// directory doesn't exist.
+    #[inline]
+    fn normalise_vendor_file<P>(vendor_dir: P) -> std::io::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(vendor_dir
+            .as_ref()
+            .to_path_buf()
+            .canonicalize()?
+            .join(VENDOR_FILE_NAME))
+    }
+
+    /// Read the vendored sources recorded in the given directory.
+    ///
+    /// Error if the directory or vendor file do not exist, if a deserialisation error occurs
+    /// when reading the vendor file, or if a recorded path isn't within the vendor directory.
+    pub fn read<P>(vendor_dir: P) -> Result<Self, crate::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let vendor_file = Self::normalise_vendor_file(vendor_dir)?;
+        let contents = std::fs::read_to_string(&vendor_file)?;
+        let items: BTreeMap<SourceName, Artefact> = serde_json::from_str(&contents)?;
+        // Recorded paths are joined onto the vendor directory and may be deleted, so they must not
+        // escape it
+        for (name, artefact) in &items {
+            let path = artefact.path();
+            let is_contained = path.components().next().is_some()
+                && path
+                    .components()
+                    .all(|c| matches!(c, std::path::Component::Normal(_)));
+            if !is_contained {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "vendored source '{name}' has a path outside the vendor directory: {}",
+                        path.display()
+                    ),
+                )
+                .into());
+            }
+        }
+        Ok(Self { items, vendor_file })
+    }
+
+    /// Read the vendored sources recorded in the given directory, or record no sources if the
+    /// vendor file does not exist. Requires that `vendor_dir` exists. The vendor file is only
+    /// created when saved.
+    ///
+    /// Returns an error if `vendor_dir` doesn't exist, or if a deserialisation error occurs when
+    /// reading the vendor file.
+    pub fn load_or_create<P>(vendor_dir: P) -> Result<Self, crate::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let vendor_file = Self::normalise_vendor_file(&vendor_dir)?;
+        if vendor_file.is_file() {
+            Self::read(vendor_dir)
+        } else {
+            Ok(Self {
+                items: BTreeMap::new(),
+                vendor_file,
+            })
+        }
+    }
+
+    /// Check whether the vendor file exists in the given directory.
+    pub fn vendor_file_exists<P>(vendor_dir: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        vendor_dir.as_ref().join(VENDOR_FILE_NAME).is_file()
+    }
+
+    /// Saves the record of vendored sources in the vendor directory.
+    ///
+    /// Returns an error if a serialisation or I/O error occurs.
+    pub fn save(&self) -> Result<(), crate::Error> {
+        let json = serde_json::to_string_pretty(&self.items)?;
+        Ok(std::fs::write(&self.vendor_file, json)?)
+    }
+
+    /// Get the vendor file path.
+    pub fn vendor_file(&self) -> &Path {
+        &self.vendor_file
+    }
+
+    /// Get the vendor directory.
+    pub fn vendor_dir(&self) -> &Path {
+        // SAFETY: `normalise_vendor_file` always joins a file name onto the directory
+        self.vendor_file.parent().unwrap()
+    }
+
+    /// The path, relative to the vendor directory, where a source with this name is vendored.
+    /// Each `::`-separated component of the name maps onto a subdirectory.
+    pub fn relative_path<S: AsRef<str>>(name: S) -> PathBuf {
+        Source::as_path_component(name)
+    }
+
+    /// Record that `source` was vendored under `name`. The source is expected to have been copied
+    /// to [`Vendor::relative_path`] within the vendor directory. Replaces any previous record for
+    /// this name.
+    pub fn insert(&mut self, name: SourceName, source: Source) {
+        let path = Self::relative_path(&name);
+        self.items.insert(name, Artefact::new(source, path));
+    }
+
+    /// Remove the record for the named source, returning it if it existed. Note that the
+    /// vendored files are not removed.
+    pub fn remove(&mut self, name: &str) -> Option<Artefact> {
+        self.items.remove(name)
+    }
+
+    /// Get the absolute path to the vendored copy of `source`, if it was vendored under `name`
+    /// with the same definition.
+    pub fn get(&self, name: &str, source: &Source) -> Option<PathBuf> {
+        self.items
+            .get(name)
+            .filter(|artefact| artefact.source() == source)
+            .map(|artefact| self.vendor_dir().join(artefact.path()))
+    }
+
+    /// Returns an iterator over the names of the vendored sources and their records. The path of
+    /// each record is relative to the vendor directory.
+    pub fn iter(&self) -> impl Iterator<Item = (&SourceName, &Artefact)> {
+        self.items.iter()
+    }
+
+    /// Split `sources` into those with a vendored copy, paired with the path to the copy, and
+    /// those which must be fetched.
+    pub fn partition(&self, sources: SourcesTable) -> (Vec<(SourceName, PathBuf)>, SourcesTable) {
+        let mut vendored = Vec::new();
+        let mut missing = SourcesTable::new();
+        for (name, source) in sources {
+            match self.get(&name, &source) {
+                Some(path) => vendored.push((name, path)),
+                None => {
+                    missing.insert(name, source);
+                }
+            }
+        }
+        (vendored, missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn vendored_source_requires_matching_definition() {
+        let dir = tempdir().unwrap();
+        let mut vendor = Vendor::load_or_create(&dir).unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        vendor.insert("foo::bar".to_string(), source.clone());
+
+        assert_eq!(
+            vendor.get("foo::bar", &source),
+            Some(dir.path().canonicalize().unwrap().join("foo/bar"))
+        );
+        assert_eq!(vendor.get("foo", &source), None);
+        let changed = Source::git("https://example.com/repo.git", None, true);
+        assert_eq!(vendor.get("foo::bar", &changed), None);
+    }
+
+    #[test]
+    fn vendor_read_rejects_paths_outside_vendor_dir() {
+        let dir = tempdir().unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        for path in ["../outside", "/tmp/outside", "foo/../../outside", ""] {
+            let items = serde_json::json!({
+                "repo": { "source": source, "path": path },
+            });
+            std::fs::write(dir.path().join(VENDOR_FILE_NAME), items.to_string()).unwrap();
+            assert!(Vendor::read(&dir).is_err(), "{path}");
+        }
+        let items = serde_json::json!({ "repo": { "source": source, "path": "foo/repo" } });
+        std::fs::write(dir.path().join(VENDOR_FILE_NAME), items.to_string()).unwrap();
+        assert!(Vendor::read(&dir).is_ok());
+    }
+
+    #[test]
+    fn vendor_save_read_roundtrip() {
+        let dir = tempdir().unwrap();
+        assert!(!Vendor::vendor_file_exists(&dir));
+        let mut vendor = Vendor::load_or_create(&dir).unwrap();
+        let source = Source::git("https://example.com/repo.git", None, false);
+        vendor.insert("repo".to_string(), source.clone());
+        vendor.save().unwrap();
+        assert!(Vendor::vendor_file_exists(&dir));
+
+        let vendor = Vendor::read(&dir).unwrap();
+        let mut sources = SourcesTable::new();
+        sources.insert("repo".to_string(), source);
+        sources.insert(
+            "other".to_string(),
+            Source::git("https://example.com/other.git", None, false),
+        );
+        let (vendored, missing) = vendor.partition(sources);
+        assert_eq!(vendored.len(), 1);
+        assert_eq!(vendored[0].0, "repo");
+        assert_eq!(missing.len(), 1);
+        assert!(missing.contains_key("other"));
+    }
+}
@@ -0,0 +1,330 @@
+//! Support for aggregating the sources declared across a cargo workspace.
+//!
+//! Sources may be declared in the `workspace.metadata.fetch-source` table of the workspace root
+//! manifest, and in the `package.metadata.fetch-source` table of any workspace member. The sources
+//! of a workspace are the union of all of these tables.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::interpolate::Variables;
+use crate::source::{SourceOptionsTable, SourceParseError, SourcesTable};
+use crate::{Diagnostic, Error, try_parse_toml_with_variables};
+
+/// The manifests of a cargo workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    root_manifest: PathBuf,
+    member_manifests: Vec<PathBuf>,
+}
+
+impl Workspace {
+    /// Read the workspace whose root manifest is `manifest`, expanding the globs in its
+    /// `workspace.members` and `workspace.exclude` keys.
+    ///
+    /// Returns an error if the manifest can't be read or has no `workspace` table.
+    pub fn read<P: AsRef<Path>>(manifest: P) -> Result<Self, Error> {
+        let root_manifest = normalize(manifest.as_ref())?;
+        let table = std::fs::read_to_string(&root_manifest)?.parse::<toml::Table>()?;
+        let workspace = table
+            .get("workspace")
+            .and_then(|v| v.as_table())
+            .ok_or(SourceParseError::WorkspaceTableNotFound)?;
+        // SAFETY: an absolute path to a file always has a parent
+        let root_dir = root_manifest.parent().unwrap();
+        let exclude = expand_globs(root_dir, workspace.get("exclude"))?;
+        let mut member_manifests = Vec::new();
+        for member in expand_globs(root_dir, workspace.get("members"))? {
+            let member_manifest = member.join("Cargo.toml");
+            if !exclude.contains(&member)
+                && member_manifest.is_file()
+                && member_manifest != root_manifest
+            {
+                member_manifests.push(member_manifest);
+            }
+        }
+        // The root manifest may also define a package, which is implicitly a member
+        if table.contains_key("package") {
+            member_manifests.insert(0, root_manifest.clone());
+        }
+        Ok(Self {
+            root_manifest,
+            member_manifests,
+        })
+    }
+
+    /// Find the workspace containing the package whose manifest is `manifest`. As with cargo, this
+    /// is the first manifest with a `workspace` table in the directory of `manifest` or any
+    /// parent directory.
+    pub fn find<P: AsRef<Path>>(manifest: P) -> Result<Self, Error> {
+        Self::try_find(manifest)?.ok_or(SourceParseError::WorkspaceTableNotFound.into())
+    }
+
+    /// As [`Workspace::find`], but returns `None` if `manifest` isn't in a workspace.
+    pub fn try_find<P: AsRef<Path>>(manifest: P) -> Result<Option<Self>, Error> {
+        let manifest = normalize(manifest.as_ref())?;
+        let mut dir = manifest.parent().map(Path::to_path_buf);
+        while let Some(current) = dir {
+            let candidate = current.join("Cargo.toml");
+            if candidate.is_file() {
+                let table = std::fs::read_to_string(&candidate)?.parse::<toml::Table>()?;
+                if table.contains_key("workspace") {
+                    return Self::read(candidate).map(Some);
+                }
+            }
+            dir = current.parent().map(Path::to_path_buf);
+        }
+        Ok(None)
+    }
+
+    /// The path of the workspace root manifest
+    pub fn root_manifest(&self) -> &Path {
+        &self.root_manifest
+    }
+
+    /// The manifests of the workspace members, including the root manifest if it defines a
+    /// package
+    pub fn member_manifests(&self) -> &[PathBuf] {
+        &self.member_manifests
+    }
+
+    /// Load the union of the sources declared in the workspace root and by all members. Manifests
+    /// without a sources table are ignored.
+    ///
+    /// Returns an error if any manifest can't be loaded, or if sources with the same name are
+    /// declared differently.
+    pub fn load_sources(
+        &self,
+        variables: &Variables,
+    ) -> Result<(SourcesTable, SourceOptionsTable), Error> {
+        let mut tables = Vec::new();
+        let root_document = std::fs::read_to_string(&self.root_manifest)?;
+        let root = ignore_missing_table(try_parse_workspace_toml_with_variables(
+            &root_document,
+            variables,
+        ))
+        .map_err(|err| Diagnostic::new(err, &self.root_manifest, Some(&root_document)))?;
+        tables.extend(root);
+        for manifest in &self.member_manifests {
+            let document = std::fs::read_to_string(manifest)?;
+            let member = ignore_missing_table(try_parse_toml_with_variables(&document, variables))
+                .map_err(|err| Diagnostic::new(err, manifest, Some(&document)))?;
+            tables.extend(member);
+        }
+        // Conflicts are reported against the root manifest, as for `cargo` workspace errors
+        union_sources(tables)
+            .map_err(|err| Diagnostic::new(err, &self.root_manifest, Some(&root_document)).into())
+    }
+}
+
+/// Make `path` absolute and remove any `.` and `..` components, as cargo does, so that its
+/// parents are the directories containing it
+fn normalize(path: &Path) -> std::io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+fn ignore_missing_table<T>(
+    result: Result<T, SourceParseError>,
+) -> Result<Option<T>, SourceParseError> {
+    match result {
+        Ok(table) => Ok(Some(table)),
+        Err(SourceParseError::SourceTableNotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Expand the glob patterns in a list of paths relative to `root_dir`
+fn expand_globs(root_dir: &Path, patterns: Option<&toml::Value>) -> std::io::Result<Vec<PathBuf>> {
+    let Some(patterns) = patterns.and_then(|v| v.as_array()) else {
+        return Ok(Vec::new());
+    };
+    let root_pattern = glob::Pattern::escape(&root_dir.to_string_lossy());
+    let mut paths = Vec::new();
+    for pattern in patterns.iter().filter_map(|v| v.as_str()) {
+        let pattern = format!("{root_pattern}/{pattern}");
+        let matches = glob::glob(&pattern)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        for path in matches {
+            paths.push(path.map_err(std::io::Error::from)?);
+        }
+    }
+    Ok(paths)
+}
+
+/// Parse the contents of a workspace root Cargo.toml file containing the
+/// `workspace.metadata.fetch-source` table into a [`SourcesTable`] map and the
+/// [`SourceOptions`](crate::SourceOptions) of each source. `${target}` is read from the `TARGET`
+/// environment variable, as in a build script.
+pub fn try_parse_workspace_toml<S: AsRef<str>>(
+    toml_str: S,
+) -> Result<(SourcesTable, SourceOptionsTable), SourceParseError> {
+    try_parse_workspace_toml_with_variables(toml_str, &Variables::from_env())
+}
+
+/// Parse the contents of a workspace root Cargo.toml file containing the
+/// `workspace.metadata.fetch-source` table, expanding the given [`Variables`].
+pub fn try_parse_workspace_toml_with_variables<S: AsRef<str>>(
+    toml_str: S,
+    variables: &Variables,
+) -> Result<(SourcesTable, SourceOptionsTable), SourceParseError> {
+    let table = toml_str.as_ref().parse::<toml::Table>()?;
+    let sources_table = table
+        .get("workspace")
+        .and_then(|v| v.get("metadata"))
+        .and_then(|v| v.get("fetch-source"))
+        .and_then(|v| v.as_table())
+        .ok_or(SourceParseError::SourceTableNotFound)?;
+    crate::source::try_parse_with_options(sources_table, variables)
+}
+
+/// Combine several sources tables into one. Sources declared more than once under the same name
+/// must have identical definitions and options.
+pub fn union_sources<I>(tables: I) -> Result<(SourcesTable, SourceOptionsTable), SourceParseError>
+where
+    I: IntoIterator<Item = (SourcesTable, SourceOptionsTable)>,
+{
+    let mut sources = SourcesTable::new();
+    let mut options = SourceOptionsTable::new();
+    for (table_sources, mut table_options) in tables {
+        for (name, source) in table_sources {
+            let source_options = table_options.remove(&name).unwrap_or_default();
+            match sources.get(&name) {
+                Some(existing)
+                    if *existing != source || options.get(&name) != Some(&source_options) =>
+                {
+                    return Err(SourceParseError::SourceConflict { source_name: name });
+                }
+                Some(_) => {}
+                None => {
+                    options.insert(name.clone(), source_options);
+                    sources.insert(name, source);
+                }
+            }
+        }
+    }
+    // Sources from different tables may still overlap
+    crate::source::check_all_destinations(&sources, &options)?;
+    Ok((sources, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::try_parse_toml_with_options;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn workspace_sources_are_the_union_of_all_tables() {
+        let root = tempfile::tempdir().unwrap();
+        write(
+            &root.path().join("Cargo.toml"),
+            r#"
+            [workspace]
+            members = ["crates/*"]
+            exclude = ["crates/excluded"]
+
+            [workspace.metadata.fetch-source]
+            shared = { git = "git@github.com:foo/shared.git" }
+            "#,
+        );
+        write(
+            &root.path().join("crates/a/Cargo.toml"),
+            r#"
+            [package.metadata.fetch-source]
+            shared = { git = "git@github.com:foo/shared.git" }
+            a = { git = "git@github.com:foo/a.git" }
+            "#,
+        );
+        write(
+            &root.path().join("crates/b/Cargo.toml"),
+            "[package]\nname = \"b\"\n",
+        );
+        write(
+            &root.path().join("crates/excluded/Cargo.toml"),
+            r#"
+            [package.metadata.fetch-source]
+            excluded = { git = "git@github.com:foo/excluded.git" }
+            "#,
+        );
+
+        let workspace = Workspace::find(root.path().join("crates/a/Cargo.toml")).unwrap();
+        assert_eq!(workspace.root_manifest(), root.path().join("Cargo.toml"));
+        assert_eq!(workspace.member_manifests().len(), 2);
+        let (sources, _) = workspace.load_sources(&Variables::new()).unwrap();
+        let mut names = sources.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "shared"]);
+    }
+
+    #[test]
+    fn conflicting_definitions_fail() {
+        let first = try_parse_workspace_toml(
+            r#"
+            [workspace.metadata.fetch-source]
+            foo = { git = "git@github.com:foo/foo.git" }
+            "#,
+        )
+        .unwrap();
+        let second = try_parse_toml_with_options(
+            r#"
+            [package.metadata.fetch-source]
+            foo = { git = "git@github.com:foo/foo.git", branch = "dev" }
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            union_sources([first, second]),
+            Err(SourceParseError::SourceConflict { source_name }) if source_name == "foo"
+        ));
+    }
+
+    #[test]
+    fn conflicts_are_located_in_the_root_manifest() {
+        let root = tempfile::tempdir().unwrap();
+        write(
+            &root.path().join("Cargo.toml"),
+            r#"[workspace]
+members = ["a"]
+[workspace.metadata.fetch-source]
+foo = { git = "git@github.com:foo/foo.git" }
+"#,
+        );
+        write(
+            &root.path().join("a/Cargo.toml"),
+            r#"[package.metadata.fetch-source]
+foo = { git = "git@github.com:foo/foo.git", branch = "dev" }
+"#,
+        );
+        let workspace = Workspace::read(root.path().join("Cargo.toml")).unwrap();
+        let err = workspace.load_sources(&Variables::new()).unwrap_err();
+        assert_eq!(err.kind(), &crate::ErrorKind::Parse);
+        let expected = format!("{}:4:1", root.path().join("Cargo.toml").display());
+        assert!(err.to_string().contains(&expected), "{err}");
+    }
+
+    #[test]
+    fn manifest_paths_are_normalized() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            normalize(Path::new("Cargo.toml")).unwrap(),
+            cwd.join("Cargo.toml")
+        );
+        assert_eq!(
+            normalize(Path::new("a/../b/./Cargo.toml")).unwrap(),
+            cwd.join("b/Cargo.toml")
+        );
+    }
+}